@@ -32,6 +32,10 @@ impl<T> Bindings<T> {
         self.0.get(name)
     }
 
+    pub fn iter(&self) -> impl Iterator<Item = (&Ident, &T)> {
+        self.0.iter()
+    }
+
     pub fn dump<W: core::fmt::Write>(&self, writer: &mut W) -> Result<(), core::fmt::Error> {
         for (ident, _) in self.0.iter() {
             writeln!(writer, "{:?}", ident)?
@@ -63,6 +67,15 @@ impl<T> GlobalBindings<T> {
         }
     }
 
+    /// Get all the bindings defined directly in a namespace
+    pub fn namespace(&self, namespace: &Namespace) -> Option<&Bindings<T>> {
+        if namespace.is_root() {
+            Some(&self.root)
+        } else {
+            self.ns.get(namespace)
+        }
+    }
+
     pub fn get(&self, name: &Path) -> Option<&T> {
         let (namespace, ident) = name.split();
         if namespace.is_root() {
@@ -0,0 +1,131 @@
+//! Free variables analysis for lambdas
+//!
+//! A lambda can refer to parameters and local bindings of the functions enclosing it,
+//! those need to be captured when the closure value is created, and are given to the
+//! lambda's code as extra parameters after the normal ones.
+
+use alloc::vec::Vec;
+use werbolg_core as ir;
+use werbolg_core::Ident;
+
+struct FreeVariables<F> {
+    bound: Vec<Ident>,
+    found: Vec<Ident>,
+    is_outer_local: F,
+}
+
+/// Collect the identifiers that the function definition uses but doesn't bind itself,
+/// and that are bound locally in the enclosing function, in order of first use.
+pub(crate) fn free_variables<F>(fundef: &ir::FunDef, is_outer_local: F) -> Vec<Ident>
+where
+    F: Fn(&Ident) -> bool,
+{
+    let mut fv = FreeVariables {
+        bound: Vec::new(),
+        found: Vec::new(),
+        is_outer_local,
+    };
+    fv.fundef(fundef);
+    fv.found
+}
+
+impl<F: Fn(&Ident) -> bool> FreeVariables<F> {
+    fn fundef(&mut self, fundef: &ir::FunDef) {
+        let scope = self.bound.len();
+        for var in fundef.vars.iter() {
+            self.bound.push(var.0.inner.clone());
+        }
+        self.expr(&fundef.body);
+        self.bound.truncate(scope);
+    }
+
+    fn ident(&mut self, ident: &Ident) {
+        if self.bound.contains(ident) || self.found.contains(ident) {
+            return;
+        }
+        if (self.is_outer_local)(ident) {
+            self.found.push(ident.clone())
+        }
+    }
+
+    fn expr(&mut self, expr: &ir::Expr) {
+        match expr {
+            ir::Expr::Literal(_, _) => {}
+            ir::Expr::Path(_, path) => {
+                if let Some(ident) = path.get_local() {
+                    self.ident(ident)
+                }
+            }
+            ir::Expr::Field(expr, _, _) | ir::Expr::FieldByName(expr, _) => self.expr(expr),
+            ir::Expr::Construct(_, _, fields) => {
+                for (_, e) in fields {
+                    self.expr(e)
+                }
+            }
+            ir::Expr::List(_, exprs) | ir::Expr::Call(_, exprs) => {
+                for e in exprs {
+                    self.expr(e)
+                }
+            }
+            ir::Expr::Let(binder, body, in_expr) => {
+                self.expr(body);
+                let scope = self.bound.len();
+                if let ir::Binder::Ident(ident) = binder {
+                    self.bound.push(ident.clone());
+                }
+                self.expr(in_expr);
+                self.bound.truncate(scope);
+            }
+            ir::Expr::Lambda(_, fundef) => self.fundef(fundef),
+            ir::Expr::If {
+                span: _,
+                cond,
+                then_expr,
+                else_expr,
+            } => {
+                self.expr(&cond.inner);
+                self.expr(&then_expr.inner);
+                self.expr(&else_expr.inner);
+            }
+            ir::Expr::Match {
+                span: _,
+                expr,
+                arms,
+            } => {
+                self.expr(expr);
+                for arm in arms {
+                    let scope = self.bound.len();
+                    pattern_binds(&arm.pattern.inner, &mut self.bound);
+                    self.expr(&arm.body);
+                    self.bound.truncate(scope);
+                }
+            }
+            ir::Expr::Try {
+                span: _,
+                body,
+                binder,
+                handler,
+            } => {
+                self.expr(body);
+                let scope = self.bound.len();
+                self.bound.push(binder.inner.clone());
+                self.expr(handler);
+                self.bound.truncate(scope);
+            }
+            ir::Expr::Throw(_, expr) => self.expr(expr),
+        }
+    }
+}
+
+/// Append all the identifiers bound by a pattern, in order
+pub(crate) fn pattern_binds(pattern: &ir::Pattern, binds: &mut Vec<Ident>) {
+    match pattern {
+        ir::Pattern::Wildcard | ir::Pattern::Literal(_) => {}
+        ir::Pattern::Bind(ident) => binds.push(ident.clone()),
+        ir::Pattern::Constructor(_, patterns) => {
+            for p in patterns {
+                pattern_binds(&p.inner, binds)
+            }
+        }
+    }
+}
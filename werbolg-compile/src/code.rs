@@ -1,6 +1,9 @@
 use super::instructions::Instruction;
+use super::location::{LineEntry, Locations};
 use super::symbols::{IdVec, IdVecAfter};
+use alloc::vec::Vec;
 use werbolg_core::id::{IdArith, IdF};
+use werbolg_core::Span;
 
 /// Instruction Address
 #[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
@@ -17,6 +20,11 @@ impl InstructionAddress {
     pub fn next(self) -> Self {
         InstructionAddress::add(self, InstructionDiff(1))
     }
+
+    /// Decrement the instruction address to the previous instruction
+    pub fn prev(self) -> Self {
+        Self(self.0.checked_sub(1).expect("ID valid sub"))
+    }
 }
 
 impl IdF for InstructionAddress {
@@ -72,6 +80,7 @@ impl core::fmt::Display for InstructionAddress {
 pub struct Code {
     stmts: IdVec<InstructionAddress, Instruction>,
     temps: usize,
+    locations: Locations,
 }
 
 /// placeholder instruction
@@ -79,13 +88,14 @@ pub struct CodeRef(InstructionAddress);
 
 /// A displacement type between instruction. i.e. the number of element between 2 different InstructionAddress
 #[derive(Debug, Copy, Clone)]
-pub struct InstructionDiff(u32);
+pub struct InstructionDiff(pub(crate) u32);
 
 impl Code {
     pub fn new() -> Self {
         Self {
             stmts: IdVec::new(),
             temps: 0,
+            locations: Locations::new(),
         }
     }
 
@@ -109,14 +119,22 @@ impl Code {
         self.temps -= 1;
     }
 
+    /// Record the source location of the last instruction pushed
+    pub fn locate_last(&mut self, namespace: u32, span: Span) {
+        let ia = self.position().prev();
+        self.locations.record(ia, namespace, span)
+    }
+
     pub fn merge(&mut self, later: Code) -> InstructionDiff {
         let ofs = self.stmts.next_id();
         self.stmts
             .concat(&mut IdVecAfter::from_idvec(later.stmts, ofs));
-        InstructionDiff(ofs.as_index() as u32)
+        let diff = InstructionDiff(ofs.as_index() as u32);
+        self.locations.merge(later.locations, diff);
+        diff
     }
 
-    pub fn finalize(self) -> IdVec<InstructionAddress, Instruction> {
-        self.stmts
+    pub fn finalize(self) -> (IdVec<InstructionAddress, Instruction>, Vec<LineEntry>) {
+        (self.stmts, self.locations.finalize())
     }
 }
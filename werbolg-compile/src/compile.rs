@@ -1,4 +1,5 @@
 use super::bindings::{BindingsStack, GlobalBindings};
+use super::captures::{free_variables, pattern_binds};
 use super::code::*;
 use super::defs::*;
 use super::errors::*;
@@ -6,8 +7,11 @@ use super::instructions::*;
 use super::symbols::*;
 use super::CompilationParams;
 use alloc::{vec, vec::Vec};
+use hashbrown::HashMap;
 use werbolg_core as ir;
-use werbolg_core::{ConstrId, FunId, GlobalId, Ident, LitId, NifId, Path, Span};
+use werbolg_core::{
+    ConstrId, FieldId, FunId, GlobalId, Ident, LitId, Namespace, NifId, Path, Span, Spanned,
+};
 
 pub(crate) struct RewriteState<'a, L: Clone + Eq + core::hash::Hash> {
     pub(crate) params: &'a CompilationParams<L>,
@@ -15,11 +19,17 @@ pub(crate) struct RewriteState<'a, L: Clone + Eq + core::hash::Hash> {
     pub(crate) funs_vec: IdVec<FunId, FunDef>,
     pub(crate) constrs: SymbolsTableData<ConstrId, ConstrDef>,
     pub(crate) lits: UniqueTableBuilder<LitId, L>,
+    pub(crate) fields: UniqueTableBuilder<FieldId, Ident>,
     pub(crate) main_code: Code,
     pub(crate) lambdas: IdVecAfter<FunId, FunDef>,
     pub(crate) lambdas_code: Code,
+    pub(crate) lambda_current: Code,
     pub(crate) in_lambda: CodeState,
     pub(crate) globals: GlobalBindings<BindingType>,
+    pub(crate) resolvers: HashMap<Namespace, NamespaceResolver>,
+    pub(crate) namespace: Namespace,
+    pub(crate) namespaces: Vec<Namespace>,
+    pub(crate) private_funs: HashMap<FunId, Namespace>,
 }
 
 pub struct LocalBindings {
@@ -57,6 +67,18 @@ impl LocalBindings {
         }
     }
 
+    /// Allocate a local without any binding, for values only used by the generated code
+    pub fn add_temp(&mut self) -> LocalBindIndex {
+        match self.local.last_mut() {
+            None => panic!("internal error: cannot add local without an empty binding stack"),
+            Some(x) => {
+                let local = *x;
+                *x += 1;
+                LocalBindIndex(local)
+            }
+        }
+    }
+
     pub fn scope_enter(&mut self) {
         let top = self.local.last().unwrap();
         self.local.push(*top);
@@ -97,7 +119,10 @@ impl<'a, L: Clone + Eq + core::hash::Hash> RewriteState<'a, L> {
         params: &'a CompilationParams<L>,
         funs_tbl: SymbolsTable<FunId>,
         lambdas: IdVecAfter<FunId, FunDef>,
+        constrs: SymbolsTableData<ConstrId, ConstrDef>,
         globals: GlobalBindings<BindingType>,
+        resolvers: HashMap<Namespace, NamespaceResolver>,
+        private_funs: HashMap<FunId, Namespace>,
     ) -> Self {
         Self {
             params,
@@ -106,13 +131,26 @@ impl<'a, L: Clone + Eq + core::hash::Hash> RewriteState<'a, L> {
             main_code: Code::new(),
             lambdas,
             lambdas_code: Code::new(),
-            constrs: SymbolsTableData::new(),
+            lambda_current: Code::new(),
+            constrs,
             lits: UniqueTableBuilder::new(),
+            fields: UniqueTableBuilder::new(),
             in_lambda: CodeState::default(),
             globals,
+            resolvers,
+            namespace: Namespace::root(),
+            namespaces: Vec::new(),
+            private_funs,
         }
     }
 
+    /// Get the resolver for the namespace of the function being compiled
+    fn resolver(&self) -> &NamespaceResolver {
+        self.resolvers
+            .get(&self.namespace)
+            .expect("resolver for every module namespace")
+    }
+
     #[must_use = "code state need to be restore using restore_codestate"]
     fn set_in_lambda(&mut self) -> CodeState {
         let saved = self.in_lambda;
@@ -127,21 +165,42 @@ impl<'a, L: Clone + Eq + core::hash::Hash> RewriteState<'a, L> {
     fn get_instruction_address(&self) -> InstructionAddress {
         match self.in_lambda {
             CodeState::InMain => self.main_code.position(),
-            CodeState::InLambda => self.lambdas_code.position(),
+            CodeState::InLambda => self.lambda_current.position(),
         }
     }
 
     fn write_code(&mut self) -> &mut Code {
         match self.in_lambda {
             CodeState::InMain => &mut self.main_code,
-            CodeState::InLambda => &mut self.lambdas_code,
+            CodeState::InLambda => &mut self.lambda_current,
         }
     }
+
+    /// Record the span of the current namespace as the source location of the last instruction written
+    fn locate_last(&mut self, span: Span) {
+        let namespace = match self.namespaces.iter().position(|ns| ns == &self.namespace) {
+            Some(index) => index,
+            None => {
+                self.namespaces.push(self.namespace.clone());
+                self.namespaces.len() - 1
+            }
+        };
+        self.write_code().locate_last(namespace as u32, span)
+    }
 }
 
+/// Generate the code for a function definition
+///
+/// The captured identifiers are available to the function body as extra parameters,
+/// that are placed after the normal parameters of the function.
+///
+/// A recursive lambda is given the name it refers to itself by, which is bound to the function
+/// value of the call frame, placed on the stack just before the parameters
 pub(crate) fn generate_func_code<'a, L: Clone + Eq + core::hash::Hash>(
     state: &mut RewriteState<'a, L>,
     fundef: ir::FunDef,
+    captures: &[Ident],
+    recursive: Option<Ident>,
 ) -> Result<FunDef, CompilationError> {
     let ir::FunDef {
         privacy: _,
@@ -159,15 +218,25 @@ pub(crate) fn generate_func_code<'a, L: Clone + Eq + core::hash::Hash>(
         .map(|n| CallArity(n))
         .map_err(|_| CompilationError::FunctionParamsMoreThanLimit(vars.len()))?;
 
-    for (var_i, var) in vars.iter().enumerate() {
-        let var_i = var_i
+    // parameters are pushed in order on the stack, so the last parameter is the closest
+    // to the stack pointer and has the index 0
+    let nb_params = vars.len() + captures.len();
+    let params = vars.iter().map(|var| &var.0.inner).chain(captures.iter());
+    for (var_i, ident) in params.enumerate() {
+        let var_i = (nb_params - 1 - var_i)
+            .try_into()
+            .map_err(|_| CompilationError::FunctionParamsMoreThanLimit(nb_params))?;
+        local.add_param(ident.clone(), var_i);
+    }
+    if let Some(ident) = recursive {
+        let fun_i = nb_params
             .try_into()
-            .map_err(|_| CompilationError::FunctionParamsMoreThanLimit(vars.len()))?;
-        local.add_param(var.0.clone().unspan(), var_i);
+            .map_err(|_| CompilationError::FunctionParamsMoreThanLimit(nb_params + 1))?;
+        local.add_param(ident, fun_i);
     }
 
     let code_pos = state.get_instruction_address();
-    generate_expression_code(state, &mut local, body.clone())?;
+    generate_expression_code(state, &mut local, body.clone(), true)?;
 
     let stack_size = local.scope_terminate();
 
@@ -180,43 +249,57 @@ pub(crate) fn generate_func_code<'a, L: Clone + Eq + core::hash::Hash>(
     })
 }
 
+/// Generate the code for an expression
+///
+/// `tail` indicates whether the expression is in tail position of the function body,
+/// in which case a call doesn't need to return to the current function and can
+/// reuse its frame
 fn generate_expression_code<'a, L: Clone + Eq + core::hash::Hash>(
     state: &mut RewriteState<'a, L>,
     local: &mut LocalBindings,
     expr: ir::Expr,
+    tail: bool,
 ) -> Result<(), CompilationError> {
     match expr {
-        ir::Expr::Literal(_span, lit) => {
+        ir::Expr::Literal(span, lit) => {
             let lit_id = state.lits.add((state.params.literal_mapper)(lit)?);
             state.write_code().push(Instruction::PushLiteral(lit_id));
+            state.locate_last(span);
             Ok(())
         }
         ir::Expr::Path(span, path) => {
-            let x = fetch_ident(state, local, span, path.clone())?;
-            match x {
-                BindingType::Global(idx) => {
-                    state.write_code().push(Instruction::FetchGlobal(idx));
-                }
-                BindingType::Nif(idx) => {
-                    state.write_code().push(Instruction::FetchNif(idx));
-                }
-                BindingType::Fun(idx) => {
-                    state.write_code().push(Instruction::FetchFun(idx));
+            let x = match fetch_ident(state, local, span.clone(), path.clone()) {
+                Ok(x) => x,
+                Err(e) => {
+                    let Some(variant) = fetch_variant(state, &path) else {
+                        return Err(e);
+                    };
+                    return generate_variant_code(state, span, path, variant, 0);
                 }
-                BindingType::Local(idx) => {
-                    state.write_code().push(Instruction::FetchStackLocal(idx));
-                }
-                BindingType::Param(idx) => {
-                    state.write_code().push(Instruction::FetchStackParam(idx));
-                }
-            }
+            };
+            state.write_code().push(fetch_instruction(x));
             Ok(())
         }
-        ir::Expr::List(_span, _l) => {
-            todo!()
+        ir::Expr::List(_span, l) => {
+            let len = l
+                .len()
+                .try_into()
+                .map(ListLength)
+                .map_err(|_| CompilationError::ListElementsMoreThanLimit(l.len()))?;
+            for e in l {
+                generate_expression_code(state, local, e, false)?;
+            }
+            state.write_code().push(Instruction::MakeList(len));
+            Ok(())
         }
         ir::Expr::Let(binder, body, in_expr) => {
-            generate_expression_code(state, local, *body)?;
+            match (&binder, *body) {
+                // a function bound to a name can refer to itself by this name
+                (ir::Binder::Ident(ident), ir::Expr::Lambda(span, fundef)) => {
+                    generate_lambda_code(state, local, span, *fundef, Some(ident))?
+                }
+                (_, body) => generate_expression_code(state, local, body, false)?,
+            }
             match binder {
                 ir::Binder::Ident(ident) => {
                     let bind = append_ident(local, &ident);
@@ -230,13 +313,13 @@ fn generate_expression_code<'a, L: Clone + Eq + core::hash::Hash>(
                     state.write_code().push(Instruction::IgnoreOne);
                 }
             }
-            generate_expression_code(state, local, *in_expr)?;
+            generate_expression_code(state, local, *in_expr, tail)?;
             Ok(())
         }
         ir::Expr::Field(expr, struct_ident, field_ident) => {
             let (constr_id, constr_def) = state
                 .constrs
-                .get(&NamespaceResolver::none(), &struct_ident.inner)
+                .get(state.resolver(), &struct_ident.inner)
                 .ok_or(CompilationError::MissingConstructor(
                     struct_ident.span.clone(),
                     struct_ident.inner.clone(),
@@ -257,28 +340,124 @@ fn generate_expression_code<'a, L: Clone + Eq + core::hash::Hash>(
                 ));
             };
 
-            generate_expression_code(state, local, *expr)?;
+            generate_expression_code(state, local, *expr, false)?;
             state
                 .write_code()
                 .push(Instruction::AccessField(constr_id, index));
+            state.locate_last(field_ident.span);
             Ok(())
         }
-        ir::Expr::Lambda(_span, fundef) => {
-            let prev = state.set_in_lambda();
-            generate_func_code(state, *fundef)?;
+        ir::Expr::FieldByName(expr, field_ident) => {
+            generate_expression_code(state, local, *expr, false)?;
+            let field_id = state.fields.add(field_ident.inner);
+            state
+                .write_code()
+                .push(Instruction::AccessFieldByName(field_id));
+            state.locate_last(field_ident.span);
+            Ok(())
+        }
+        ir::Expr::Construct(span, struct_path, fields) => {
+            let Some((constr_id, constr_def)) =
+                state.constrs.get(state.resolver(), &struct_path.inner)
+            else {
+                return Err(CompilationError::MissingConstructor(
+                    struct_path.span,
+                    struct_path.inner,
+                ));
+            };
+
+            let ConstrDef::Struct(struct_def) = constr_def else {
+                return Err(CompilationError::ConstructorNotStructure(
+                    struct_path.span,
+                    struct_path.inner,
+                ));
+            };
 
-            state.restore_codestate(prev);
-            todo!()
+            // index in the structure of every field, in the construction order
+            let mut indexes = Vec::with_capacity(fields.len());
+            for (field, _) in fields.iter() {
+                let Some(index) = struct_def.find_field_index(&field.inner) else {
+                    return Err(CompilationError::StructureFieldNotExistant(
+                        field.span.clone(),
+                        struct_path.inner,
+                        field.inner.clone(),
+                    ));
+                };
+                if indexes.contains(&(index.0 as usize)) {
+                    return Err(CompilationError::StructureFieldDuplicate(
+                        field.span.clone(),
+                        struct_path.inner,
+                        field.inner.clone(),
+                    ));
+                }
+                indexes.push(index.0 as usize);
+            }
+            let nb_fields = struct_def.fields.len();
+            let arity = u8::try_from(nb_fields)
+                .map(CallArity)
+                .map_err(|_| CompilationError::StructureFieldsMoreThanLimit(nb_fields))?;
+            if let Some(missing) = (0..nb_fields).find(|i| !indexes.contains(i)) {
+                return Err(CompilationError::StructureFieldMissing(
+                    span,
+                    struct_path.inner,
+                    struct_def.fields[missing].clone(),
+                ));
+            }
+
+            if indexes.iter().enumerate().all(|(i, index)| i == *index) {
+                for (_, e) in fields {
+                    generate_expression_code(state, local, e, false)?;
+                }
+            } else {
+                // the fields are evaluated in the construction order, then pushed in the structure order
+                let mut temps = vec![None; nb_fields];
+                for ((_, e), index) in fields.into_iter().zip(indexes) {
+                    generate_expression_code(state, local, e, false)?;
+                    let temp = local.add_temp();
+                    state.write_code().push(Instruction::LocalBind(temp));
+                    temps[index] = Some(temp);
+                }
+                for temp in temps {
+                    let temp = temp.expect("all fields are given");
+                    state.write_code().push(Instruction::FetchStackLocal(temp));
+                }
+            }
+            state
+                .write_code()
+                .push(Instruction::MakeStruct(constr_id, arity));
+            Ok(())
         }
-        ir::Expr::Call(_span, args) => {
+        ir::Expr::Lambda(span, fundef) => generate_lambda_code(state, local, span, *fundef, None),
+        ir::Expr::Call(span, mut args) => {
             assert!(args.len() > 0);
             let len = args.len() - 1;
+
+            // calling an enumeration variant (that is not shadowed by a binding) constructs it
+            if let ir::Expr::Path(_, path) = &args[0] {
+                let variant = fetch_ident(state, local, span.clone(), path.clone())
+                    .err()
+                    .and_then(|_| fetch_variant(state, path));
+                if let Some(variant) = variant {
+                    let ir::Expr::Path(_, path) = args.remove(0) else {
+                        unreachable!()
+                    };
+                    for arg in args {
+                        generate_expression_code(state, local, arg, false)?;
+                    }
+                    return generate_variant_code(state, span, path, variant, len);
+                }
+            }
+
             for arg in args {
-                generate_expression_code(state, local, arg)?;
+                generate_expression_code(state, local, arg, false)?;
             }
-            state
-                .write_code()
-                .push(Instruction::Call(CallArity(len as u8)));
+            let arity = CallArity(len as u8);
+            if tail {
+                state.write_code().push(Instruction::TailCall(arity));
+            } else {
+                state.write_code().push(Instruction::Call(arity));
+            }
+            state.locate_last(span);
             Ok(())
         }
         ir::Expr::If {
@@ -287,20 +466,22 @@ fn generate_expression_code<'a, L: Clone + Eq + core::hash::Hash>(
             then_expr,
             else_expr,
         } => {
-            generate_expression_code(state, local, (*cond).unspan())?;
+            let cond_span = cond.span.clone();
+            generate_expression_code(state, local, (*cond).unspan(), false)?;
 
             let cond_jump_ref = state.write_code().push_temp();
+            state.locate_last(cond_span);
             let cond_pos = state.get_instruction_address();
 
             local.scope_enter();
-            generate_expression_code(state, local, (*then_expr).unspan())?;
+            generate_expression_code(state, local, (*then_expr).unspan(), tail)?;
             local.scope_leave();
 
             let jump_else_ref = state.write_code().push_temp();
             let else_pos = state.get_instruction_address();
 
             local.scope_enter();
-            generate_expression_code(state, local, (*else_expr).unspan())?;
+            generate_expression_code(state, local, (*else_expr).unspan(), tail)?;
             local.scope_leave();
 
             let end_pos = state.get_instruction_address();
@@ -314,6 +495,388 @@ fn generate_expression_code<'a, L: Clone + Eq + core::hash::Hash>(
 
             Ok(())
         }
+        ir::Expr::Match { span, expr, arms } => {
+            generate_expression_code(state, local, *expr, false)?;
+            let value = local.add_temp();
+            state.write_code().push(Instruction::LocalBind(value));
+            generate_match_code(state, local, span, value, arms, tail)
+        }
+        ir::Expr::Try {
+            span: _,
+            body,
+            binder,
+            handler,
+        } => {
+            let push_handler_ref = state.write_code().push_temp();
+            let push_handler_pos = state.get_instruction_address();
+
+            // the handler need to stay registered during the calls of the body,
+            // so they can't reuse the frame
+            local.scope_enter();
+            generate_expression_code(state, local, *body, false)?;
+            local.scope_leave();
+            state.write_code().push(Instruction::PopHandler);
+
+            let jump_end_ref = state.write_code().push_temp();
+            let handler_pos = state.get_instruction_address();
+
+            local.scope_enter();
+            let bind = local.add_local(binder.inner);
+            state.write_code().push(Instruction::LocalBind(bind));
+            generate_expression_code(state, local, *handler, tail)?;
+            local.scope_leave();
+
+            let end_pos = state.get_instruction_address();
+
+            state.write_code().resolve_temp(
+                push_handler_ref,
+                Instruction::PushHandler(handler_pos - push_handler_pos),
+            );
+            state
+                .write_code()
+                .resolve_temp(jump_end_ref, Instruction::Jump(end_pos - handler_pos));
+            Ok(())
+        }
+        ir::Expr::Throw(span, expr) => {
+            generate_expression_code(state, local, *expr, false)?;
+            state.write_code().push(Instruction::Throw);
+            state.locate_last(span);
+            Ok(())
+        }
+    }
+}
+
+/// A pattern with its constructors and literals resolved, and its bindings allocated to locals
+enum MatchPattern {
+    Any,
+    Bind(LocalBindIndex),
+    Literal(LitId),
+    Constructor {
+        constr: ConstrId,
+        /// The variant and the number of variants for an enumeration, None for a structure
+        variant: Option<(VariantIndex, SwitchLength)>,
+        /// The local holding the value, for a constructor nested in another
+        slot: Option<LocalBindIndex>,
+        /// The fields to match, the fields matching anything are not present
+        fields: Vec<(StructFieldIndex, MatchPattern)>,
+    },
+}
+
+/// A resolved constructor of a pattern: the constructor, the variant and the number of variants
+/// for an enumeration, and the number of fields
+type PatternConstructor = (ConstrId, Option<(VariantIndex, SwitchLength)>, usize);
+
+/// A jump to resolve once its destination is known, either a Jump or a MatchLiteral
+struct PendingJump(CodeRef, InstructionAddress, Option<LitId>);
+
+impl PendingJump {
+    fn push<'a, L: Clone + Eq + core::hash::Hash>(
+        state: &mut RewriteState<'a, L>,
+        literal: Option<LitId>,
+    ) -> Self {
+        let code_ref = state.write_code().push_temp();
+        Self(code_ref, state.get_instruction_address(), literal)
+    }
+
+    fn resolve<'a, L: Clone + Eq + core::hash::Hash>(self, state: &mut RewriteState<'a, L>) {
+        let diff = state.get_instruction_address() - self.1;
+        let instruction = match self.2 {
+            None => Instruction::Jump(diff),
+            Some(lit_id) => Instruction::MatchLiteral(lit_id, diff),
+        };
+        state.write_code().resolve_temp(self.0, instruction)
+    }
+}
+
+/// Generate the code for the arms of a match, the value matched being in a local
+///
+/// The code is laid out as the tests of the patterns followed by the arm bodies. When the arms
+/// are constructors of the same enumeration, the tests start with a switch on the variant, and
+/// only the arms that can match the variant are tested
+fn generate_match_code<'a, L: Clone + Eq + core::hash::Hash>(
+    state: &mut RewriteState<'a, L>,
+    local: &mut LocalBindings,
+    span: Span,
+    value: LocalBindIndex,
+    arms: Vec<ir::MatchArm>,
+    tail: bool,
+) -> Result<(), CompilationError> {
+    // every arm scope starts at the same local, so that its bindings get the same locals
+    // when the pattern is tested and when the body is generated
+    let mut patterns = Vec::with_capacity(arms.len());
+    let mut bodies = Vec::with_capacity(arms.len());
+    for arm in arms {
+        let mut binds = Vec::new();
+        pattern_binds(&arm.pattern.inner, &mut binds);
+
+        local.scope_enter();
+        let mut bind_locals = Vec::with_capacity(binds.len());
+        for ident in binds.iter() {
+            bind_locals.push(local.add_local(ident.clone()));
+        }
+        let pattern = resolve_pattern(state, local, &mut bind_locals.into_iter(), arm.pattern);
+        local.scope_leave();
+
+        patterns.push(pattern?);
+        bodies.push((binds, arm.body));
+    }
+
+    let dispatch = match_dispatch(&patterns);
+    let mut matched: Vec<Vec<PendingJump>> = patterns.iter().map(|_| Vec::new()).collect();
+
+    match dispatch {
+        None => {
+            generate_match_block(state, &span, value, &patterns, &mut matched, |_| true, true);
+        }
+        Some((constr, len)) => {
+            state.write_code().push(Instruction::FetchStackLocal(value));
+            state.write_code().push(Instruction::Switch(constr, len));
+            let table = (0..=len.0)
+                .map(|_| PendingJump::push(state, None))
+                .collect::<Vec<_>>();
+            for (entry, jump) in table.into_iter().enumerate() {
+                jump.resolve(state);
+                generate_match_block(
+                    state,
+                    &span,
+                    value,
+                    &patterns,
+                    &mut matched,
+                    |pattern| match pattern {
+                        MatchPattern::Constructor {
+                            variant: Some((variant, _)),
+                            ..
+                        } => variant.0 as usize == entry,
+                        _ => true,
+                    },
+                    false,
+                );
+            }
+        }
+    }
+
+    let nb_arms = bodies.len();
+    let mut ends = Vec::with_capacity(nb_arms);
+    for (i, ((binds, body), jumps)) in bodies.into_iter().zip(matched).enumerate() {
+        for jump in jumps {
+            jump.resolve(state);
+        }
+        local.scope_enter();
+        for ident in binds {
+            local.add_local(ident);
+        }
+        generate_expression_code(state, local, body, tail)?;
+        local.scope_leave();
+        if i + 1 < nb_arms {
+            ends.push(PendingJump::push(state, None));
+        }
+    }
+    for jump in ends {
+        jump.resolve(state);
+    }
+    Ok(())
+}
+
+/// Check if the arms can be dispatched by a switch on the value, which is the case when
+/// the patterns are variants of the same enumeration or patterns matching any value
+fn match_dispatch(patterns: &[MatchPattern]) -> Option<(ConstrId, SwitchLength)> {
+    let mut dispatch = None;
+    for pattern in patterns {
+        match pattern {
+            MatchPattern::Any | MatchPattern::Bind(_) => {}
+            MatchPattern::Constructor {
+                constr,
+                variant: Some((_, len)),
+                ..
+            } => match dispatch {
+                None => dispatch = Some((*constr, *len)),
+                Some((c, _)) if c == *constr => {}
+                Some(_) => return None,
+            },
+            _ => return None,
+        }
+    }
+    dispatch
+}
+
+/// Generate the tests of the arms selected by the filter, in order, jumping to the arm body
+/// on the first match, and failing if none match
+fn generate_match_block<'a, L: Clone + Eq + core::hash::Hash, F: Fn(&MatchPattern) -> bool>(
+    state: &mut RewriteState<'a, L>,
+    span: &Span,
+    value: LocalBindIndex,
+    patterns: &[MatchPattern],
+    matched: &mut [Vec<PendingJump>],
+    filter: F,
+    check_constr: bool,
+) {
+    for (i, pattern) in patterns.iter().enumerate() {
+        if !filter(pattern) {
+            continue;
+        }
+        let mut fails = Vec::new();
+        generate_pattern_test(state, value, pattern, check_constr, &mut fails);
+        matched[i].push(PendingJump::push(state, None));
+        if fails.is_empty() {
+            // the arms after an irrefutable pattern are not reachable
+            return;
+        }
+        for jump in fails {
+            jump.resolve(state);
+        }
+    }
+    state
+        .write_code()
+        .push(Instruction::MatchFailure(span.clone()));
+}
+
+/// Generate the code testing that the value in the local matches the pattern, and binding its
+/// identifiers. The jumps to take when the value doesn't match are added to fails
+fn generate_pattern_test<'a, L: Clone + Eq + core::hash::Hash>(
+    state: &mut RewriteState<'a, L>,
+    value: LocalBindIndex,
+    pattern: &MatchPattern,
+    check_constr: bool,
+    fails: &mut Vec<PendingJump>,
+) {
+    match pattern {
+        MatchPattern::Any => {}
+        MatchPattern::Bind(bind) => {
+            state.write_code().push(Instruction::FetchStackLocal(value));
+            state.write_code().push(Instruction::LocalBind(*bind));
+        }
+        MatchPattern::Literal(lit_id) => {
+            state.write_code().push(Instruction::FetchStackLocal(value));
+            fails.push(PendingJump::push(state, Some(*lit_id)));
+        }
+        MatchPattern::Constructor {
+            constr,
+            variant,
+            slot: _,
+            fields,
+        } => {
+            if check_constr {
+                // a structure is a switch with a single entry
+                let (index, len) = variant.unwrap_or((VariantIndex(0), SwitchLength(1)));
+                state.write_code().push(Instruction::FetchStackLocal(value));
+                state.write_code().push(Instruction::Switch(*constr, len));
+                let mut matching = None;
+                for entry in 0..=len.0 {
+                    let jump = PendingJump::push(state, None);
+                    if entry == index.0 {
+                        matching = Some(jump)
+                    } else {
+                        fails.push(jump)
+                    }
+                }
+                matching.expect("entry in table").resolve(state);
+            }
+            for (field, field_pattern) in fields {
+                state.write_code().push(Instruction::FetchStackLocal(value));
+                state.write_code().push(match variant {
+                    None => Instruction::AccessField(*constr, *field),
+                    Some(_) => Instruction::AccessVariantField(*constr, *field),
+                });
+                match field_pattern {
+                    MatchPattern::Any => {
+                        state.write_code().push(Instruction::IgnoreOne);
+                    }
+                    MatchPattern::Bind(bind) => {
+                        state.write_code().push(Instruction::LocalBind(*bind));
+                    }
+                    MatchPattern::Literal(lit_id) => {
+                        fails.push(PendingJump::push(state, Some(*lit_id)));
+                    }
+                    MatchPattern::Constructor { slot, .. } => {
+                        let slot = slot.expect("nested constructor has a local");
+                        state.write_code().push(Instruction::LocalBind(slot));
+                        generate_pattern_test(state, slot, field_pattern, true, fails);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Resolve the constructors and literals of a pattern, giving to its bindings the locals in order
+fn resolve_pattern<'a, L: Clone + Eq + core::hash::Hash>(
+    state: &mut RewriteState<'a, L>,
+    local: &mut LocalBindings,
+    binds: &mut impl Iterator<Item = LocalBindIndex>,
+    pattern: Spanned<ir::Pattern>,
+) -> Result<MatchPattern, CompilationError> {
+    match pattern.inner {
+        ir::Pattern::Wildcard => Ok(MatchPattern::Any),
+        ir::Pattern::Bind(_) => Ok(MatchPattern::Bind(
+            binds.next().expect("local for every binding"),
+        )),
+        ir::Pattern::Literal(lit) => {
+            let lit_id = state.lits.add((state.params.literal_mapper)(lit)?);
+            Ok(MatchPattern::Literal(lit_id))
+        }
+        ir::Pattern::Constructor(path, patterns) => {
+            let (constr, variant, expected) = resolve_constructor(state, &path)?;
+            if expected != patterns.len() {
+                return Err(CompilationError::ConstructorArityMismatch {
+                    span: path.span,
+                    path: path.inner,
+                    expected,
+                    got: patterns.len(),
+                });
+            }
+            let mut fields = Vec::new();
+            for (i, field_pattern) in patterns.into_iter().enumerate() {
+                let mut field_pattern = resolve_pattern(state, local, binds, field_pattern)?;
+                if let MatchPattern::Constructor { slot, .. } = &mut field_pattern {
+                    *slot = Some(local.add_temp());
+                }
+                if !matches!(field_pattern, MatchPattern::Any) {
+                    let index = i
+                        .try_into()
+                        .map(StructFieldIndex)
+                        .map_err(|_| CompilationError::StructureFieldsMoreThanLimit(expected))?;
+                    fields.push((index, field_pattern));
+                }
+            }
+            Ok(MatchPattern::Constructor {
+                constr,
+                variant,
+                slot: None,
+                fields,
+            })
+        }
+    }
+}
+
+/// Resolve the path of a constructor pattern, either an enumeration variant or a structure,
+/// returning the constructor, the variant if any, and the number of fields
+fn resolve_constructor<'a, L: Clone + Eq + core::hash::Hash>(
+    state: &RewriteState<'a, L>,
+    path: &Spanned<Path>,
+) -> Result<PatternConstructor, CompilationError> {
+    if let Some((constr, variant, nb_fields)) = fetch_variant(state, &path.inner) {
+        let ConstrDef::Enum(enum_def) = &state.constrs.vecdata[constr] else {
+            panic!("internal error: variant of a constructor not an enumeration")
+        };
+        let nb_variants = enum_def.variants.len();
+        let len = nb_variants
+            .try_into()
+            .map(SwitchLength)
+            .map_err(|_| CompilationError::EnumVariantsMoreThanLimit(nb_variants))?;
+        return Ok((constr, Some((variant, len)), nb_fields));
+    }
+    match state.constrs.get(state.resolver(), &path.inner) {
+        Some((constr, ConstrDef::Struct(struct_def))) => {
+            Ok((constr, None, struct_def.fields.len()))
+        }
+        Some((_, ConstrDef::Enum(_))) => Err(CompilationError::ConstructorNotStructure(
+            path.span.clone(),
+            path.inner.clone(),
+        )),
+        None => Err(CompilationError::MissingConstructor(
+            path.span.clone(),
+            path.inner.clone(),
+        )),
     }
 }
 
@@ -323,12 +886,130 @@ fn fetch_ident<'a, L: Clone + Eq + core::hash::Hash>(
     span: Span,
     path: Path,
 ) -> Result<BindingType, CompilationError> {
-    local
-        .bindings
-        .get(&path)
-        .or_else(|| state.globals.get(&path))
-        .map(|x| *x)
-        .ok_or(CompilationError::MissingSymbol(span, path))
+    if let Some(binding) = local.bindings.get(&path) {
+        return Ok(*binding);
+    }
+    let candidates = state.resolver().candidates(&path);
+    for candidate in candidates.iter() {
+        if let Some(binding) = state.globals.get(candidate) {
+            if let BindingType::Fun(fun_id) = binding {
+                let fun_namespace = state.private_funs.get(fun_id);
+                if fun_namespace.is_some_and(|ns| *ns != state.namespace) {
+                    return Err(CompilationError::PrivateSymbol(span, candidate.clone()));
+                }
+            }
+            return Ok(*binding);
+        }
+    }
+    Err(CompilationError::MissingSymbol(span, path, candidates))
+}
+
+/// Resolve a path of the form `Enum::Variant` to the enumeration constructor, the index of the variant
+/// and its number of fields
+fn fetch_variant<'a, L: Clone + Eq + core::hash::Hash>(
+    state: &RewriteState<'a, L>,
+    path: &Path,
+) -> Option<(ConstrId, VariantIndex, usize)> {
+    if path.len().get() < 2 {
+        return None;
+    }
+    let (namespace, variant_ident) = path.split();
+    let enum_path = Path::new_raw(path.path_type(), namespace.iter().cloned().collect());
+    let (constr_id, ConstrDef::Enum(enum_def)) = state.constrs.get(state.resolver(), &enum_path)?
+    else {
+        return None;
+    };
+    let index = enum_def
+        .variants
+        .iter()
+        .position(|v| v.name == variant_ident)?;
+    let ConstrDef::Struct(fields) = &state.constrs.vecdata[enum_def.variants[index].constr] else {
+        return None;
+    };
+    Some((constr_id, VariantIndex(index as u8), fields.fields.len()))
+}
+
+/// Generate the construction of an enumeration variant, once its fields are on the stack
+fn generate_variant_code<'a, L: Clone + Eq + core::hash::Hash>(
+    state: &mut RewriteState<'a, L>,
+    span: Span,
+    path: Path,
+    (constr_id, variant_index, expected): (ConstrId, VariantIndex, usize),
+    got: usize,
+) -> Result<(), CompilationError> {
+    if expected != got {
+        return Err(CompilationError::ConstructorArityMismatch {
+            span,
+            path,
+            expected,
+            got,
+        });
+    }
+    state.write_code().push(Instruction::MakeEnum(
+        constr_id,
+        variant_index,
+        CallArity(got as u8),
+    ));
+    Ok(())
+}
+
+/// Generate the code creating the function value of a lambda, the lambda code itself is generated
+/// in the lambdas code.
+///
+/// `name` is the identifier the lambda is bound to, if any, which makes the lambda recursive
+/// when its body refers to it
+fn generate_lambda_code<'a, L: Clone + Eq + core::hash::Hash>(
+    state: &mut RewriteState<'a, L>,
+    local: &mut LocalBindings,
+    span: Span,
+    fundef: ir::FunDef,
+    name: Option<&Ident>,
+) -> Result<(), CompilationError> {
+    let name = name.filter(|name| !free_variables(&fundef, |ident| ident == *name).is_empty());
+    let captures = free_variables(&fundef, |ident| {
+        Some(ident) != name && local.bindings.get(&Path::relative(ident.clone())).is_some()
+    });
+    let nb_captures = captures
+        .len()
+        .try_into()
+        .map(CallArity)
+        .map_err(|_| CompilationError::FunctionParamsMoreThanLimit(captures.len()))?;
+
+    for ident in captures.iter() {
+        let x = fetch_ident(state, local, span.clone(), Path::relative(ident.clone()))?;
+        state.write_code().push(fetch_instruction(x));
+    }
+
+    // the lambda code is generated in its own buffer, as lambdas can be nested,
+    // and only appended to the lambdas code once complete
+    let prev = state.set_in_lambda();
+    let outer_code = core::mem::replace(&mut state.lambda_current, Code::new());
+    let lambda_def = generate_func_code(state, fundef, &captures, name.cloned());
+    let lambda_code = core::mem::replace(&mut state.lambda_current, outer_code);
+    state.restore_codestate(prev);
+
+    let mut lambda_def = lambda_def?;
+    lambda_def.code_pos += state.lambdas_code.merge(lambda_code);
+    let fun_id = state.lambdas.push(lambda_def);
+
+    if captures.is_empty() {
+        state.write_code().push(Instruction::FetchFun(fun_id));
+    } else {
+        state
+            .write_code()
+            .push(Instruction::MakeClosure(fun_id, nb_captures));
+    }
+    Ok(())
+}
+
+fn fetch_instruction(binding: BindingType) -> Instruction {
+    match binding {
+        BindingType::Global(idx) => Instruction::FetchGlobal(idx),
+        BindingType::Nif(idx) => Instruction::FetchNif(idx),
+        BindingType::Fun(idx) => Instruction::FetchFun(idx),
+        BindingType::Local(idx) => Instruction::FetchStackLocal(idx),
+        BindingType::Param(idx) => Instruction::FetchStackParam(idx),
+    }
 }
 
 fn append_ident(local: &mut LocalBindings, ident: &Ident) -> LocalBindIndex {
@@ -1,25 +1,54 @@
-use werbolg_core::{Ident, Literal, Path, Span};
+use werbolg_core::{Ident, Literal, Namespace, Path, Span};
 
 use super::symbols::NamespaceError;
-use alloc::{boxed::Box, format, string::String};
+use alloc::{boxed::Box, format, string::String, vec::Vec};
 
 /// Compilation error
 #[derive(Debug)]
 pub enum CompilationError {
     /// Duplicate symbol during compilation (e.g. 2 functions with the name)
     DuplicateSymbol(Ident),
-    /// Cannot find the symbol during compilation
-    MissingSymbol(Span, Path),
+    /// Cannot find the symbol during compilation, with all the candidate paths tried
+    MissingSymbol(Span, Path, Vec<Path>),
+    /// The symbol is private to another namespace
+    PrivateSymbol(Span, Path),
     /// Cannot find the constructor symbol during compilation
     MissingConstructor(Span, Path),
+    /// A symbol hidden or renamed in a use statement doesn't exist in the imported namespace
+    ImportMissingSymbol(Namespace, Ident),
+    /// Two use statements of the same module bring the same symbol in scope
+    ImportConflict(Ident, Path, Path),
     /// Number of parameters for a functions is above the limit we chose
     FunctionParamsMoreThanLimit(usize),
+    /// Number of elements in a list is above the limit we chose
+    ListElementsMoreThanLimit(usize),
+    /// Number of variants in an enumeration is above the limit we chose
+    EnumVariantsMoreThanLimit(usize),
+    /// Number of fields in a structure construction is above the limit we chose
+    StructureFieldsMoreThanLimit(usize),
+    /// Number of fields in an enumeration variant is above the limit we chose
+    VariantFieldsMoreThanLimit(usize),
+    /// The constructor is used with a number of fields (got) different from its definition (expected)
+    ConstructorArityMismatch {
+        /// Span of the construction or pattern
+        span: Span,
+        /// Path of the constructor
+        path: Path,
+        /// Number of fields in the constructor definition
+        expected: usize,
+        /// Number of fields given
+        got: usize,
+    },
     /// Core's Literal is not supported by this compiler
     LiteralNotSupported(Literal),
     /// The constructor specified is a not a structure, but trying to access inner field
     ConstructorNotStructure(Span, Path),
     /// The structure specified doesn't have a field of the right name
     StructureFieldNotExistant(Span, Path, Ident),
+    /// The structure construction doesn't give a value to a field
+    StructureFieldMissing(Span, Path, Ident),
+    /// The structure construction gives a value to a field more than once
+    StructureFieldDuplicate(Span, Path, Ident),
     /// Namespace Error
     NamespaceError(NamespaceError),
     /// A recursive compilation with some context added
@@ -1,5 +1,5 @@
 use super::code::InstructionDiff;
-use werbolg_core::{ConstrId, FunId, GlobalId, LitId, NifId};
+use werbolg_core::{ConstrId, FieldId, FunId, GlobalId, LitId, NifId, Span};
 
 /// Instruction for execution
 #[derive(Clone, Debug)]
@@ -12,12 +12,28 @@ pub enum Instruction {
     FetchNif(NifId),
     /// Fetch from the fun array
     FetchFun(FunId),
+    /// Create a closure value of the function FunId, capturing the N values on the top of the stack
+    ///
+    /// The captured values are given to the function as extra parameters, after the call arguments
+    MakeClosure(FunId, CallArity),
     /// Fetch from the callstack param (which is relative and before SP)
     FetchStackParam(ParamBindIndex),
     /// Fetch from the localstack values (which is relative and after SP)
     FetchStackLocal(LocalBindIndex),
+    /// Create a list value from the N values on the top of the stack
+    MakeList(ListLength),
+    /// Create an enumeration value of the given variant, from the N values on the top of the stack as fields
+    MakeEnum(ConstrId, VariantIndex, CallArity),
+    /// Create a structure value, from the N values on the top of the stack as fields
+    MakeStruct(ConstrId, CallArity),
     /// Access a field in a structure value as stack\[top\]
     AccessField(ConstrId, StructFieldIndex),
+    /// Access a field by name in a structure value as stack\[top\]
+    ///
+    /// The index of the field is found at runtime from the structure definition of the value
+    AccessFieldByName(FieldId),
+    /// Access a field in an enumeration value as stack\[top\]
+    AccessVariantField(ConstrId, StructFieldIndex),
     /// Bind Locally a value
     LocalBind(LocalBindIndex),
     /// Ignore a value from the stack
@@ -26,12 +42,37 @@ pub enum Instruction {
     ///
     /// expecting N+1 value on the value stack
     Call(CallArity),
+    /// Call the function on the stack with the N value in arguments, reusing the current call frame.
+    ///
+    /// This is used for calls in tail position, where the current function would
+    /// directly return the value of the call. expecting N+1 value on the value stack
+    TailCall(CallArity),
     /// Jump by N instructions
     Jump(InstructionDiff),
     /// Jump by N instructions if stack\[top\] is true
     CondJump(InstructionDiff),
+    /// Switch on the constructor of stack\[top\]
+    ///
+    /// This is followed by a jump table of N+1 instructions. A value of the enumeration continues
+    /// at the entry of its variant, a structure of the constructor at the first entry,
+    /// and any other value at the last entry
+    Switch(ConstrId, SwitchLength),
+    /// Jump by N instructions if stack\[top\] doesn't match the literal
+    MatchLiteral(LitId, InstructionDiff),
+    /// Fail as no pattern of the match at this location matched the value
+    MatchFailure(Span),
     /// Return from call
     Ret,
+    /// Register a handler for the errors thrown until the matching `PopHandler`
+    ///
+    /// The handler code is N instructions after this one, plus 1, and starts with the error
+    /// value on the top of the stack, the call frames and the values pushed since the
+    /// registration being discarded
+    PushHandler(InstructionDiff),
+    /// Unregister the innermost handler, as the code it protects completed
+    PopHandler,
+    /// Throw stack\[top\] to the innermost handler
+    Throw,
 }
 
 /// The index of locally (in the context of a function) bind value
@@ -44,12 +85,26 @@ pub struct LocalBindIndex(pub u16);
 #[derive(Clone, Copy, Debug)]
 pub struct ParamBindIndex(pub u8);
 
+/// The number of elements in a list construction
+#[derive(Clone, Copy, Debug)]
+pub struct ListLength(pub u32);
+
 /// A field in a structured indexed by its order in the structure
 ///
 /// This is limited (arbitrarily) to a maximum of 255
 #[derive(Clone, Copy, Debug)]
 pub struct StructFieldIndex(pub u8);
 
+/// A variant in an enumeration indexed by its order in the enumeration
+///
+/// This is limited (arbitrarily) to a maximum of 255
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct VariantIndex(pub u8);
+
+/// The number of entries in a switch jump table, not counting the last entry for other values
+#[derive(Clone, Copy, Debug)]
+pub struct SwitchLength(pub u8);
+
 /// The arity (number of parameter) of a function.
 ///
 /// This is limited (arbitrarily) to a maximum of 255
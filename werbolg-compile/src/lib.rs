@@ -5,32 +5,41 @@
 extern crate alloc;
 
 mod bindings;
+mod captures;
 mod code;
 mod compile;
 mod defs;
 mod environ;
 mod errors;
 mod instructions;
+mod location;
 mod params;
+mod serialize;
 mod symbols;
 
 pub use code::{InstructionAddress, InstructionDiff};
-pub use instructions::{CallArity, Instruction, LocalBindIndex, ParamBindIndex, StructFieldIndex};
+pub use instructions::{
+    CallArity, Instruction, ListLength, LocalBindIndex, ParamBindIndex, StructFieldIndex,
+    SwitchLength, VariantIndex,
+};
+pub use location::{LineEntry, LineTable};
 pub use params::CompilationParams;
+pub use serialize::{DecodeError, LiteralCodec, FORMAT_VERSION};
 
 use compile::*;
 pub use defs::*;
 use werbolg_core as ir;
-use werbolg_core::{ConstrId, FunId, LitId, Namespace, Path};
+use werbolg_core::{ConstrId, FieldId, FunId, Ident, LitId, Namespace, Path};
 
 use bindings::GlobalBindings;
 pub use environ::Environment;
 pub use errors::CompilationError;
 pub use symbols::NamespaceResolver;
-use symbols::{IdVec, IdVecAfter, SymbolsTable, SymbolsTableData};
+use symbols::{IdVec, IdVecAfter, NamespaceError, SymbolsTable, SymbolsTableData};
 
 use alloc::{format, vec::Vec};
 use core::fmt::Write;
+use hashbrown::HashMap;
 
 /// A compiled unit
 ///
@@ -41,19 +50,45 @@ pub struct CompilationUnit<L> {
     pub lits: IdVec<LitId, L>,
     /// Table of constructor (structure / enum) indexed by their ConstrId
     pub constrs: SymbolsTableData<ConstrId, ConstrDef>,
-    /// Symbol table of function { Ident => FunId }
+    /// Table of the field names accessed by name, indexed by their FieldId
+    pub fields: IdVec<FieldId, Ident>,
+    /// Symbol table of public function { Ident => FunId }
     pub funs_tbl: SymbolsTable<FunId>,
     /// Table of function indexed by their FunId
     pub funs: IdVec<FunId, FunDef>,
     /// A sequence of instructions of all the code, indexed by InstructionAddress
     pub code: IdVec<InstructionAddress, Instruction>,
+    /// The source locations of the code
+    pub lines: LineTable,
+    /// The fingerprint of the unit, computed once when compiled or decoded
+    pub(crate) fingerprint: u64,
+}
+
+impl<L> CompilationUnit<L> {
+    /// Get the function which code contains the instruction at this address
+    ///
+    /// The code of each function is contiguous, so this is the function with the closest entry point before the address
+    pub fn function_at(&self, ia: InstructionAddress) -> Option<FunId> {
+        self.funs
+            .iter()
+            .filter(|(_, fundef)| fundef.code_pos <= ia)
+            .max_by_key(|(_, fundef)| fundef.code_pos)
+            .map(|(fun_id, _)| fun_id)
+    }
+
+    /// Get the source location (namespace and span) of the instruction at this address, if it has been recorded
+    pub fn location_of(&self, ia: InstructionAddress) -> Option<(&Namespace, &ir::Span)> {
+        self.lines.find(ia)
+    }
 }
 
 /// State of compilation
 pub struct CompilationState<L: Clone + Eq + core::hash::Hash> {
     params: CompilationParams<L>,
-    funs: SymbolsTableData<FunId, ir::FunDef>,
+    funs: SymbolsTableData<FunId, (Namespace, ir::FunDef)>,
     constrs: SymbolsTableData<ConstrId, ConstrDef>,
+    uses: Vec<(Namespace, ir::Use)>,
+    resolvers: HashMap<Namespace, NamespaceResolver>,
 }
 
 impl<L: Clone + Eq + core::hash::Hash> CompilationState<L> {
@@ -63,6 +98,8 @@ impl<L: Clone + Eq + core::hash::Hash> CompilationState<L> {
             params,
             funs: SymbolsTableData::new(),
             constrs: SymbolsTableData::new(),
+            uses: Vec::new(),
+            resolvers: HashMap::new(),
         }
     }
 
@@ -74,21 +111,27 @@ impl<L: Clone + Eq + core::hash::Hash> CompilationState<L> {
     ) -> Result<(), CompilationError> {
         self.funs.create_namespace(namespace.clone())?;
         self.constrs.create_namespace(namespace.clone())?;
+        self.resolvers
+            .insert(namespace.clone(), NamespaceResolver::new(namespace.clone()));
 
         for stmt in module.statements.into_iter() {
             match stmt {
-                ir::Statement::Use(_u) => {
-                    // todo
-                    ()
+                ir::Statement::Use(u) => {
+                    // imports are resolved when finalizing, once all the modules are known
+                    self.uses.push((namespace.clone(), u));
                 }
                 ir::Statement::Function(_span, fundef) => {
                     let ident = fundef.name.clone();
                     let _funid = if let Some(ident) = ident {
                         self.funs
-                            .add(namespace, &Path::relative(ident.clone()), fundef)
+                            .add(
+                                namespace,
+                                &Path::relative(ident.clone()),
+                                (namespace.clone(), fundef),
+                            )
                             .ok_or_else(|| CompilationError::DuplicateSymbol(ident))?
                     } else {
-                        self.funs.add_anon(fundef)
+                        self.funs.add_anon((namespace.clone(), fundef))
                     };
                     ()
                 }
@@ -106,6 +149,42 @@ impl<L: Clone + Eq + core::hash::Hash> CompilationState<L> {
                         )
                         .ok_or_else(|| CompilationError::DuplicateSymbol(name))?;
                 }
+                ir::Statement::Enum(_span, enumdef) => {
+                    let nb_variants = enumdef.variants.len();
+                    if nb_variants > u8::MAX as usize {
+                        return Err(CompilationError::EnumVariantsMoreThanLimit(nb_variants));
+                    }
+                    // each variant has its own anonymous structure constructor for its fields
+                    let mut variants: Vec<Variant> = Vec::with_capacity(nb_variants);
+                    for ir::Variant(variant) in enumdef.variants.into_iter() {
+                        let name = variant.name.unspan();
+                        if variants.iter().any(|v| v.name == name) {
+                            return Err(CompilationError::DuplicateSymbol(name));
+                        }
+                        let nb_fields = variant.fields.len();
+                        if nb_fields > u8::MAX as usize {
+                            return Err(CompilationError::VariantFieldsMoreThanLimit(nb_fields));
+                        }
+                        let stru = StructDef {
+                            name: name.clone(),
+                            fields: variant.fields.into_iter().map(|v| v.unspan()).collect(),
+                        };
+                        let constr = self.constrs.add_anon(ConstrDef::Struct(stru));
+                        variants.push(Variant { name, constr });
+                    }
+                    let enu = EnumDef {
+                        name: enumdef.name.unspan(),
+                        variants,
+                    };
+                    let name = enu.name.clone();
+                    self.constrs
+                        .add(
+                            namespace,
+                            &Path::relative(name.clone()),
+                            ConstrDef::Enum(enu),
+                        )
+                        .ok_or(CompilationError::DuplicateSymbol(name))?;
+                }
                 ir::Statement::Expr(_) => (),
             }
         }
@@ -117,7 +196,13 @@ impl<L: Clone + Eq + core::hash::Hash> CompilationState<L> {
         self,
         environ: &mut Environment<N, G>,
     ) -> Result<CompilationUnit<L>, CompilationError> {
-        let SymbolsTableData { table, vecdata } = self.funs;
+        let SymbolsTableData { mut table, vecdata } = self.funs;
+
+        let private_funs = vecdata
+            .iter()
+            .filter(|(_, (_, fundef))| matches!(fundef.privacy, ir::Privacy::Private))
+            .map(|(fun_id, (namespace, _))| (fun_id, namespace.clone()))
+            .collect::<HashMap<_, _>>();
 
         let mut root_bindings = GlobalBindings::new();
         for (path, id) in environ.symbols.to_vec(Namespace::root()) {
@@ -132,19 +217,41 @@ impl<L: Clone + Eq + core::hash::Hash> CompilationState<L> {
             root_bindings.add(path, BindingType::Fun(fun_id))
         }
 
+        // private functions are only reachable from the code of their namespace, not by the host
+        table.retain(&|fun_id| !private_funs.contains_key(&fun_id));
+
+        let mut resolvers = self.resolvers;
+        for (namespace, u) in self.uses.into_iter() {
+            let resolver = resolvers
+                .get_mut(&namespace)
+                .expect("resolver created with the module");
+            add_imports(
+                &root_bindings,
+                &private_funs,
+                &self.constrs.table,
+                resolver,
+                u,
+            )
+            .map_err(|e| e.context(format!("use in module {:?}", namespace)))?;
+        }
+
         //let bindings = BindingsStack::new();
 
         let mut state = compile::RewriteState::new(
             &self.params,
             table,
             IdVecAfter::new(vecdata.next_id()),
+            self.constrs,
             //bindings,
             root_bindings,
+            resolvers,
+            private_funs,
         );
 
-        for (funid, fundef) in vecdata.into_iter() {
+        for (funid, (namespace, fundef)) in vecdata.into_iter() {
+            state.namespace = namespace;
             let fun_name = fundef.name.clone();
-            let lirdef = compile::generate_func_code(&mut state, fundef)
+            let lirdef = compile::generate_func_code(&mut state, fundef, &[], None)
                 .map_err(|e| e.context(format!("function code {:?}", fun_name)))?;
             let lirid = state.funs_vec.push(lirdef);
             assert_eq!(funid, lirid)
@@ -160,16 +267,85 @@ impl<L: Clone + Eq + core::hash::Hash> CompilationState<L> {
         state.funs_vec.concat(&mut state.lambdas);
         let funs = state.funs_vec;
 
-        Ok(CompilationUnit {
+        let (code, entries) = state.main_code.finalize();
+        let mut unit = CompilationUnit {
             lits: state.lits.finalize(),
             constrs: state.constrs,
+            fields: state.fields.finalize(),
             funs: funs,
             funs_tbl: state.funs_tbl,
-            code: state.main_code.finalize(),
-        })
+            code,
+            lines: LineTable {
+                namespaces: state.namespaces,
+                entries,
+            },
+            fingerprint: 0,
+        };
+        unit.fingerprint = unit.compute_fingerprint();
+        Ok(unit)
     }
 }
 
+/// Add the symbols that a use statement brings in scope to the imports of a module
+///
+/// All the public symbols defined in the used namespace are imported, except the hidden ones,
+/// and the renamed ones are imported under their new name
+fn add_imports(
+    globals: &GlobalBindings<BindingType>,
+    private_funs: &HashMap<FunId, Namespace>,
+    constrs: &SymbolsTable<ConstrId>,
+    resolver: &mut NamespaceResolver,
+    u: ir::Use,
+) -> Result<(), CompilationError> {
+    let namespace = Namespace::root().append(u.namespace.clone());
+
+    let globals_symbols = globals.namespace(&namespace).map(|bindings| {
+        bindings
+            .iter()
+            .filter(|(_, binding)| match binding {
+                BindingType::Fun(fun_id) => !private_funs.contains_key(fun_id),
+                _ => true,
+            })
+            .map(|(ident, _)| ident)
+    });
+    let constrs_symbols = constrs.namespace_symbols(&namespace);
+    if globals_symbols.is_none() && constrs_symbols.is_none() {
+        return Err(NamespaceError::Missing(namespace, u.namespace).into());
+    }
+    let symbols = globals_symbols
+        .into_iter()
+        .flatten()
+        .chain(constrs_symbols.into_iter().flatten())
+        .collect::<Vec<_>>();
+
+    let renamed = u.renames.iter().map(|(from, _)| from);
+    for ident in u.hiding.iter().chain(renamed) {
+        if !symbols.contains(&ident) {
+            return Err(CompilationError::ImportMissingSymbol(
+                namespace,
+                ident.clone(),
+            ));
+        }
+    }
+
+    for ident in symbols {
+        if u.hiding.contains(ident) {
+            continue;
+        }
+        let name = u
+            .renames
+            .iter()
+            .find(|(from, _)| from == ident)
+            .map(|(_, to)| to.clone())
+            .unwrap_or_else(|| ident.clone());
+        let path = namespace.path_with_ident(ident);
+        resolver
+            .import(name.clone(), path.clone())
+            .map_err(|existing| CompilationError::ImportConflict(name, existing, path))?;
+    }
+    Ok(())
+}
+
 /// Compile a IR Module into an optimised-for-execution `CompilationUnit`
 pub fn compile<'a, L: Clone + Eq + core::hash::Hash, N, G>(
     params: &'a CompilationParams<L>,
@@ -216,3 +392,195 @@ pub fn code_dump<W: Write>(
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::{boxed::Box, vec};
+    use werbolg_core::{
+        EnumDef, Expr, Literal, Module, Pattern, Privacy, Spanned, Statement, StructDef, Use,
+        Variable, Variant,
+    };
+    use werbolg_lang_common::FileUnit;
+
+    fn literal_mapper(lit: Literal) -> Result<u64, CompilationError> {
+        match lit {
+            Literal::Number(n) => n
+                .parse()
+                .map_err(|_| CompilationError::LiteralNotSupported(Literal::Number(n))),
+            _ => Err(CompilationError::LiteralNotSupported(lit)),
+        }
+    }
+
+    fn parse(snippet: &str) -> ir::Module {
+        let fileunit = FileUnit::from_str("test", snippet);
+        werbolg_lang_lispy::module(&fileunit).expect("no parse error")
+    }
+
+    /// Add a use statement at the top of a module
+    fn with_use(mut module: Module, namespace: &str, hiding: &[&str]) -> Module {
+        let u = Use {
+            namespace: Ident::from(namespace),
+            hiding: hiding.iter().map(|h| Ident::from(*h)).collect(),
+            renames: vec![],
+        };
+        module.statements.insert(0, Statement::Use(u));
+        module
+    }
+
+    fn math_module() -> (Namespace, Module) {
+        let snippet = r#"
+        (define (double x) (+ x x))
+        (define (inc x) (+ x 1))
+        "#;
+        (
+            Namespace::root().append(Ident::from("math")),
+            parse(snippet),
+        )
+    }
+
+    /// Compile the modules, with the NIF + in the root namespace, and get the error without its contexts
+    fn compile_error(modules: Vec<(Namespace, Module)>) -> Option<CompilationError> {
+        fn cause(e: CompilationError) -> CompilationError {
+            match e {
+                CompilationError::Context(_, e) => cause(*e),
+                e => e,
+            }
+        }
+        let params = CompilationParams { literal_mapper };
+        let mut environ = Environment::<(), ()>::new();
+        environ.add_nif(&Namespace::root(), Ident::from("+"), ());
+        compile(&params, modules, &mut environ).err().map(cause)
+    }
+
+    fn spanned(ident: &str) -> Spanned<Ident> {
+        Spanned::new(0..0, Ident::from(ident))
+    }
+
+    fn wide_fields(nb_fields: usize) -> Vec<Spanned<Ident>> {
+        (0..nb_fields)
+            .map(|i| spanned(&format!("f{}", i)))
+            .collect()
+    }
+
+    #[test]
+    fn use_hiding() {
+        let main = with_use(parse("(define main (inc 41))"), "math", &["inc"]);
+        assert!(matches!(
+            compile_error(vec![math_module(), (Namespace::root(), main)]),
+            Some(CompilationError::MissingSymbol(_, _, _))
+        ))
+    }
+
+    #[test]
+    fn use_conflict() {
+        let other = parse("(define (inc x) (+ x 2))");
+        let other = (Namespace::root().append(Ident::from("other")), other);
+        let main = parse("(define main (inc 41))");
+        let main = with_use(with_use(main, "math", &[]), "other", &[]);
+        assert!(matches!(
+            compile_error(vec![math_module(), other, (Namespace::root(), main)]),
+            Some(CompilationError::ImportConflict(_, _, _))
+        ))
+    }
+
+    #[test]
+    fn missing_symbol_candidates() {
+        let app = Namespace::root().append(Ident::from("app"));
+        let main = with_use(parse("(define main (halve 84))"), "math", &[]);
+        let Some(CompilationError::MissingSymbol(_, path, candidates)) =
+            compile_error(vec![math_module(), (app.clone(), main)])
+        else {
+            panic!("expecting a missing symbol")
+        };
+        assert_eq!(path, Path::relative(Ident::from("halve")));
+        // the candidates are the paths tried from the namespace of the module
+        assert_eq!(candidates, NamespaceResolver::new(app).candidates(&path));
+    }
+
+    #[test]
+    fn private_symbols() {
+        let mut root = parse("(define (secret x) (+ x 1)) (define main (secret 41))");
+        for stmt in root.statements.iter_mut() {
+            if let Statement::Function(_, fundef) = stmt {
+                if fundef.name.as_ref().is_some_and(|n| n.matches("secret")) {
+                    fundef.privacy = Privacy::Private;
+                }
+            }
+        }
+        let secret = Path::absolute(Ident::from("secret"));
+
+        // the private function is not in the table of the public functions
+        let params = CompilationParams { literal_mapper };
+        let mut environ = Environment::<(), ()>::new();
+        environ.add_nif(&Namespace::root(), Ident::from("+"), ());
+        let modules = vec![(Namespace::root(), root.clone())];
+        let unit = compile(&params, modules, &mut environ).expect("compiled");
+        assert!(unit
+            .funs_tbl
+            .get(&NamespaceResolver::none(), &secret)
+            .is_none());
+
+        let app = parse("(define (peek) (secret 1))");
+        let app = (Namespace::root().append(Ident::from("app")), app);
+        assert!(matches!(
+            compile_error(vec![(Namespace::root(), root), app]),
+            Some(CompilationError::PrivateSymbol(_, path)) if path == secret
+        ))
+    }
+
+    #[test]
+    fn enum_variant_fields_limit() {
+        let big = EnumDef {
+            name: spanned("big"),
+            variants: vec![Variant(StructDef {
+                name: spanned("wide"),
+                fields: wide_fields(256),
+            })],
+        };
+        let module = Module {
+            statements: vec![Statement::Enum(0..0, big)],
+        };
+        assert!(matches!(
+            compile_error(vec![(Namespace::root(), module)]),
+            Some(CompilationError::VariantFieldsMoreThanLimit(256))
+        ));
+    }
+
+    #[test]
+    fn struct_pattern_fields_limit() {
+        let wide = StructDef {
+            name: spanned("wide"),
+            fields: wide_fields(257),
+        };
+        // only the last field is matched, its index doesn't fit the pattern instruction
+        let mut patterns = vec![Spanned::new(0..0, Pattern::Wildcard); 256];
+        patterns.push(Spanned::new(0..0, Pattern::Bind(Ident::from("last"))));
+        let path = Spanned::new(0..0, Path::relative(Ident::from("wide")));
+        let local = |ident: &str| Expr::Path(0..0, Path::relative(Ident::from(ident)));
+        let body = Expr::Match {
+            span: 0..0,
+            expr: Box::new(local("w")),
+            arms: vec![ir::MatchArm {
+                pattern: Spanned::new(0..0, Pattern::Constructor(path, patterns)),
+                body: local("last"),
+            }],
+        };
+        let last = ir::FunDef {
+            privacy: Privacy::Public,
+            name: Some(Ident::from("last")),
+            vars: vec![Variable(spanned("w"))],
+            body,
+        };
+        let module = Module {
+            statements: vec![
+                Statement::Struct(0..0, wide),
+                Statement::Function(0..0, last),
+            ],
+        };
+        assert!(matches!(
+            compile_error(vec![(Namespace::root(), module)]),
+            Some(CompilationError::StructureFieldsMoreThanLimit(257))
+        ));
+    }
+}
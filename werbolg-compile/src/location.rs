@@ -0,0 +1,83 @@
+//! Source locations of the compiled code
+//!
+//! The line table maps ranges of instructions to the span in the source of the expression
+//! they were generated from. Only some instructions are recorded (calls, literals, field accesses
+//! and conditional jumps), which are the ones that can fail at runtime.
+
+use super::code::{InstructionAddress, InstructionDiff};
+use alloc::vec::Vec;
+use werbolg_core::{Namespace, Span};
+
+/// A range of instructions generated from the same source location
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LineEntry {
+    /// The first instruction of the range
+    pub start: InstructionAddress,
+    /// The instruction after the last instruction of the range
+    pub end: InstructionAddress,
+    /// The index of the namespace of the source, in the namespaces of the line table
+    pub namespace: u32,
+    /// The span in the source
+    pub span: Span,
+}
+
+/// Table of the source locations of the code
+#[derive(Clone, Debug, Default)]
+pub struct LineTable {
+    /// The namespaces (i.e. modules) of the source
+    pub namespaces: Vec<Namespace>,
+    /// The ranges of instructions with their location, ordered by address and not overlapping
+    pub entries: Vec<LineEntry>,
+}
+
+impl LineTable {
+    /// Find the location of the instruction at this address, if it has been recorded
+    pub fn find(&self, ia: InstructionAddress) -> Option<(&Namespace, &Span)> {
+        let index = self.entries.partition_point(|entry| entry.start <= ia);
+        let entry = &self.entries[index.checked_sub(1)?];
+        if ia < entry.end {
+            Some((&self.namespaces[entry.namespace as usize], &entry.span))
+        } else {
+            None
+        }
+    }
+}
+
+/// Ranges of instructions with their location, recorded while generating some code
+pub(crate) struct Locations(Vec<LineEntry>);
+
+impl Locations {
+    pub fn new() -> Self {
+        Self(Vec::new())
+    }
+
+    /// Record the location of the instruction at `ia`, which is after all the already recorded ones
+    pub fn record(&mut self, ia: InstructionAddress, namespace: u32, span: Span) {
+        let end = ia.next();
+        if let Some(last) = self.0.last_mut() {
+            if last.end == ia && last.namespace == namespace && last.span == span {
+                last.end = end;
+                return;
+            }
+        }
+        self.0.push(LineEntry {
+            start: ia,
+            end,
+            namespace,
+            span,
+        })
+    }
+
+    /// Append the locations of code that is placed after the code of these locations
+    pub fn merge(&mut self, later: Locations, offset: InstructionDiff) {
+        for mut entry in later.0 {
+            entry.start += offset;
+            entry.end += offset;
+            self.0.push(entry)
+        }
+    }
+
+    pub fn finalize(self) -> Vec<LineEntry> {
+        self.0
+    }
+}
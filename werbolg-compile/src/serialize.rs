@@ -0,0 +1,721 @@
+//! Binary serialization of a compilation unit
+//!
+//! The format starts with a magic and the format version, followed by the literals,
+//! the constructors, the field names, the functions, the functions symbol table, the code
+//! and finally the line table of the code.
+//!
+//! The primitives are the ones of [`werbolg_core::serialize`]. Symbol tables are written
+//! sorted by ident, so that encoding the same unit always gives the same bytes.
+
+use super::code::InstructionDiff;
+use super::defs::*;
+use super::instructions::*;
+use super::location::{LineEntry, LineTable};
+use super::symbols::{SymbolsTable, SymbolsTableData};
+use super::CompilationUnit;
+use alloc::vec::Vec;
+use core::hash::{Hash, Hasher};
+use werbolg_core::id::IdF;
+pub use werbolg_core::serialize::DecodeError;
+use werbolg_core::serialize::{Reader, Writer};
+use werbolg_core::{ConstrId, FieldId, FunId, GlobalId, LitId, NifId};
+
+const MAGIC: [u8; 4] = *b"WBLG";
+
+/// The version of the binary format written by the encoder
+pub const FORMAT_VERSION: u16 = 1;
+
+/// Namespaces nested deeper than this are rejected by the decoder
+const MAX_NAMESPACE_DEPTH: usize = 64;
+
+/// User driven serialization of the compilation-level literals
+#[derive(Clone)]
+pub struct LiteralCodec<L> {
+    /// Append the binary representation of a literal to the buffer
+    pub encode: fn(&L, &mut Vec<u8>),
+    /// Decode a literal from its binary representation, or None if it is not valid
+    pub decode: fn(&[u8]) -> Option<L>,
+}
+
+/// FNV-1a hasher, writing the integers in little endian so that the hash is the same on every platform
+struct Fnv(u64);
+
+impl Fnv {
+    fn new() -> Self {
+        Fnv(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+impl Hasher for Fnv {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 = (self.0 ^ *byte as u64).wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+
+    fn write_u16(&mut self, i: u16) {
+        self.write(&i.to_le_bytes())
+    }
+
+    fn write_u32(&mut self, i: u32) {
+        self.write(&i.to_le_bytes())
+    }
+
+    fn write_u64(&mut self, i: u64) {
+        self.write(&i.to_le_bytes())
+    }
+
+    fn write_u128(&mut self, i: u128) {
+        self.write(&i.to_le_bytes())
+    }
+
+    fn write_usize(&mut self, i: usize) {
+        self.write_u64(i as u64)
+    }
+}
+
+impl<L> CompilationUnit<L> {
+    /// Encode this compilation unit to bytes, using the literal codec for the literals
+    pub fn encode(&self, codec: &LiteralCodec<L>) -> Vec<u8> {
+        self.encode_with(codec.encode)
+    }
+
+    /// Get the fingerprint of this compilation unit, computed when the unit is compiled or decoded
+    ///
+    /// This identifies the unit that some data depends on, e.g. a snapshot of an execution
+    pub fn fingerprint(&self) -> u64 {
+        self.fingerprint
+    }
+
+    /// Compute the fingerprint of this compilation unit, as the FNV-1a hash of its encoding,
+    /// with the literals given by their hash instead of their user encoding
+    pub(crate) fn compute_fingerprint(&self) -> u64
+    where
+        L: Hash,
+    {
+        let bytes = self.encode_with(|lit, out| {
+            let mut hasher = Fnv::new();
+            lit.hash(&mut hasher);
+            out.extend_from_slice(&hasher.finish().to_le_bytes())
+        });
+        let mut hasher = Fnv::new();
+        hasher.write(&bytes);
+        hasher.finish()
+    }
+
+    fn encode_with(&self, encode_lit: impl Fn(&L, &mut Vec<u8>)) -> Vec<u8> {
+        let mut w = Writer::new(&MAGIC, FORMAT_VERSION);
+
+        w.length(self.lits.iter().count());
+        let mut lit_buf = Vec::new();
+        for (_, lit) in self.lits.iter() {
+            lit_buf.clear();
+            encode_lit(lit, &mut lit_buf);
+            w.bytes(&lit_buf);
+        }
+
+        write_symbols(&mut w, &self.constrs.table);
+        w.length(self.constrs.vecdata.iter().count());
+        for (_, constr) in self.constrs.vecdata.iter() {
+            write_constr(&mut w, constr);
+        }
+
+        w.length(self.fields.iter().count());
+        for (_, field) in self.fields.iter() {
+            w.ident(field);
+        }
+
+        w.length(self.funs.iter().count());
+        for (_, fundef) in self.funs.iter() {
+            write_fundef(&mut w, fundef);
+        }
+
+        write_symbols(&mut w, &self.funs_tbl);
+
+        w.length(self.code.iter().count());
+        for (_, instruction) in self.code.iter() {
+            write_instruction(&mut w, instruction);
+        }
+
+        w.list(self.lines.namespaces.iter(), |w, ns| w.namespace(ns));
+        w.list(self.lines.entries.iter(), |w, entry| {
+            w.id(entry.start);
+            w.id(entry.end);
+            w.u32(entry.namespace);
+            w.span(&entry.span);
+        });
+        w.finalize()
+    }
+
+    /// Decode a compilation unit from bytes, using the literal codec for the literals
+    ///
+    /// All the references between the elements of the unit (functions, constructors, literals,
+    /// instruction addresses) are checked to be valid
+    pub fn decode(data: &[u8], codec: &LiteralCodec<L>) -> Result<Self, DecodeError>
+    where
+        L: Hash,
+    {
+        let mut r = Reader::new(data, &MAGIC, FORMAT_VERSION)?;
+
+        let mut lit_index = 0;
+        let lits = r.idvec(|r| {
+            let lit_id = LitId::from_collection_len(lit_index);
+            lit_index += 1;
+            (codec.decode)(r.bytes()?).ok_or(DecodeError::InvalidLiteral(lit_id))
+        })?;
+
+        let constrs_tbl = read_symbols(&mut r, 0)?;
+        let constrs_data = r.idvec(read_constr)?;
+        let fields = r.idvec(|r| r.ident())?;
+        let funs = r.idvec(read_fundef)?;
+        let funs_tbl = read_symbols(&mut r, 0)?;
+        let code = r.idvec(read_instruction)?;
+        let lines = LineTable {
+            namespaces: r.list(|r| r.namespace())?,
+            entries: r.list(|r| {
+                Ok(LineEntry {
+                    start: r.id()?,
+                    end: r.id()?,
+                    namespace: r.u32()?,
+                    span: r.span()?,
+                })
+            })?,
+        };
+
+        r.finalize()?;
+
+        let mut unit = CompilationUnit {
+            lits,
+            constrs: SymbolsTableData {
+                table: constrs_tbl,
+                vecdata: constrs_data,
+            },
+            fields,
+            funs_tbl,
+            funs,
+            code,
+            lines,
+            fingerprint: 0,
+        };
+        unit.validate()?;
+        unit.fingerprint = unit.compute_fingerprint();
+        Ok(unit)
+    }
+
+    fn validate(&self) -> Result<(), DecodeError> {
+        let code_len = self.code.iter().count();
+        let funs_len = self.funs.iter().count();
+        let constrs_len = self.constrs.vecdata.iter().count();
+        let lits_len = self.lits.iter().count();
+        let fields_len = self.fields.iter().count();
+
+        let check = |what: &'static str, index: usize, len: usize| {
+            if index < len {
+                Ok(())
+            } else {
+                Err(DecodeError::InvalidReference {
+                    what,
+                    index: index as u32,
+                })
+            }
+        };
+
+        for (_, fun_id) in self.funs_tbl.to_vec(werbolg_core::Namespace::root()) {
+            check("function", fun_id.as_index(), funs_len)?;
+        }
+        for (_, constr_id) in self.constrs.table.to_vec(werbolg_core::Namespace::root()) {
+            check("constructor", constr_id.as_index(), constrs_len)?;
+        }
+        for (_, constr) in self.constrs.vecdata.iter() {
+            if let ConstrDef::Enum(enumdef) = constr {
+                for variant in enumdef.variants.iter() {
+                    check("constructor", variant.constr.as_index(), constrs_len)?;
+                }
+            }
+        }
+        for (_, fundef) in self.funs.iter() {
+            check("instruction", fundef.code_pos.as_index(), code_len)?;
+        }
+        for (ia, instruction) in self.code.iter() {
+            match instruction {
+                Instruction::PushLiteral(lit_id) => check("literal", lit_id.as_index(), lits_len)?,
+                Instruction::FetchFun(fun_id) | Instruction::MakeClosure(fun_id, _) => {
+                    check("function", fun_id.as_index(), funs_len)?
+                }
+                Instruction::AccessField(constr_id, _)
+                | Instruction::AccessVariantField(constr_id, _)
+                | Instruction::MakeStruct(constr_id, _) => {
+                    check("constructor", constr_id.as_index(), constrs_len)?
+                }
+                Instruction::Switch(constr_id, len) => {
+                    check("constructor", constr_id.as_index(), constrs_len)?;
+                    // the jump table follows the switch
+                    let last_entry = ia.as_index() + 1 + len.0 as usize;
+                    check("instruction", last_entry, code_len)?
+                }
+                Instruction::AccessFieldByName(field_id) => {
+                    check("field", field_id.as_index(), fields_len)?
+                }
+                Instruction::MatchLiteral(lit_id, d) => {
+                    check("literal", lit_id.as_index(), lits_len)?;
+                    let target = ia.as_index() + 1 + d.0 as usize;
+                    check("instruction", target, code_len)?
+                }
+                Instruction::MakeEnum(constr_id, variant, _) => {
+                    check("constructor", constr_id.as_index(), constrs_len)?;
+                    let nb_variants = match &self.constrs.vecdata[*constr_id] {
+                        ConstrDef::Enum(enumdef) => enumdef.variants.len(),
+                        ConstrDef::Struct(_) => 0,
+                    };
+                    check("variant", variant.0 as usize, nb_variants)?
+                }
+                Instruction::Jump(d) | Instruction::CondJump(d) | Instruction::PushHandler(d) => {
+                    let target = ia.as_index() + 1 + d.0 as usize;
+                    check("instruction", target, code_len)?
+                }
+                _ => {}
+            }
+        }
+
+        // the entries are searched by address, so they need to be ordered and not overlapping
+        let mut lines_end = 0;
+        for entry in self.lines.entries.iter() {
+            let (start, end) = (entry.start.as_index(), entry.end.as_index());
+            if start < lines_end || end <= start {
+                return Err(DecodeError::InvalidReference {
+                    what: "line entry",
+                    index: start as u32,
+                });
+            }
+            check("instruction", end - 1, code_len)?;
+            check(
+                "namespace",
+                entry.namespace as usize,
+                self.lines.namespaces.len(),
+            )?;
+            lines_end = end;
+        }
+        Ok(())
+    }
+}
+
+fn write_symbols<ID: IdF>(w: &mut Writer, table: &SymbolsTable<ID>) {
+    let mut symbols = table.current.iter().collect::<Vec<_>>();
+    symbols.sort_by(|(a, _), (b, _)| a.0.cmp(&b.0));
+    w.length(symbols.len());
+    for (ident, id) in symbols {
+        w.ident(ident);
+        w.id(id);
+    }
+
+    let mut namespaces = table.ns.iter().collect::<Vec<_>>();
+    namespaces.sort_by(|(a, _), (b, _)| a.0.cmp(&b.0));
+    w.length(namespaces.len());
+    for (ident, child) in namespaces {
+        w.ident(ident);
+        write_symbols(w, child);
+    }
+}
+
+fn read_symbols<ID: IdF>(r: &mut Reader, depth: usize) -> Result<SymbolsTable<ID>, DecodeError> {
+    if depth > MAX_NAMESPACE_DEPTH {
+        return Err(DecodeError::TooDeep);
+    }
+    let mut table = SymbolsTable::new();
+    for (ident, id) in r.list(|r| Ok((r.ident()?, r.id()?)))? {
+        table.current.insert(ident, id);
+    }
+    let nb_namespaces = r.length()?;
+    for _ in 0..nb_namespaces {
+        let ident = r.ident()?;
+        let child = read_symbols(r, depth + 1)?;
+        table.ns.insert(ident, child);
+    }
+    Ok(table)
+}
+
+fn write_constr(w: &mut Writer, constr: &ConstrDef) {
+    match constr {
+        ConstrDef::Struct(structdef) => {
+            w.u8(0);
+            w.ident(&structdef.name);
+            w.length(structdef.fields.len());
+            for field in structdef.fields.iter() {
+                w.ident(field);
+            }
+        }
+        ConstrDef::Enum(enumdef) => {
+            w.u8(1);
+            w.ident(&enumdef.name);
+            w.length(enumdef.variants.len());
+            for variant in enumdef.variants.iter() {
+                w.ident(&variant.name);
+                w.id(variant.constr);
+            }
+        }
+    }
+}
+
+fn read_constr(r: &mut Reader) -> Result<ConstrDef, DecodeError> {
+    match r.u8()? {
+        0 => Ok(ConstrDef::Struct(StructDef {
+            name: r.ident()?,
+            fields: r.list(|r| r.ident())?,
+        })),
+        1 => Ok(ConstrDef::Enum(EnumDef {
+            name: r.ident()?,
+            variants: r.list(|r| {
+                Ok(Variant {
+                    name: r.ident()?,
+                    constr: r.id()?,
+                })
+            })?,
+        })),
+        tag => Err(DecodeError::InvalidTag {
+            what: "constructor",
+            tag,
+        }),
+    }
+}
+
+fn write_fundef(w: &mut Writer, fundef: &FunDef) {
+    match &fundef.name {
+        None => w.u8(0),
+        Some(name) => {
+            w.u8(1);
+            w.ident(name)
+        }
+    }
+    w.u8(fundef.arity.0);
+    w.u16(fundef.stack_size.0);
+    w.id(fundef.code_pos);
+}
+
+fn read_fundef(r: &mut Reader) -> Result<FunDef, DecodeError> {
+    let name = match r.u8()? {
+        0 => None,
+        1 => Some(r.ident()?),
+        tag => {
+            return Err(DecodeError::InvalidTag {
+                what: "function name",
+                tag,
+            })
+        }
+    };
+    Ok(FunDef {
+        name,
+        arity: CallArity(r.u8()?),
+        stack_size: LocalStackSize(r.u16()?),
+        code_pos: r.id()?,
+    })
+}
+
+fn write_instruction(w: &mut Writer, instruction: &Instruction) {
+    match instruction {
+        Instruction::PushLiteral(lit_id) => {
+            w.u8(0);
+            w.id(*lit_id)
+        }
+        Instruction::FetchGlobal(global_id) => {
+            w.u8(1);
+            w.id(*global_id)
+        }
+        Instruction::FetchNif(nif_id) => {
+            w.u8(2);
+            w.id(*nif_id)
+        }
+        Instruction::FetchFun(fun_id) => {
+            w.u8(3);
+            w.id(*fun_id)
+        }
+        Instruction::MakeClosure(fun_id, arity) => {
+            w.u8(4);
+            w.id(*fun_id);
+            w.u8(arity.0)
+        }
+        Instruction::FetchStackParam(param_bind) => {
+            w.u8(5);
+            w.u8(param_bind.0)
+        }
+        Instruction::FetchStackLocal(local_bind) => {
+            w.u8(6);
+            w.u16(local_bind.0)
+        }
+        Instruction::MakeList(len) => {
+            w.u8(7);
+            w.u32(len.0)
+        }
+        Instruction::MakeEnum(constr_id, variant, nb_fields) => {
+            w.u8(16);
+            w.id(*constr_id);
+            w.u8(variant.0);
+            w.u8(nb_fields.0)
+        }
+        Instruction::AccessField(constr_id, field) => {
+            w.u8(8);
+            w.id(*constr_id);
+            w.u8(field.0)
+        }
+        Instruction::LocalBind(local_bind) => {
+            w.u8(9);
+            w.u16(local_bind.0)
+        }
+        Instruction::IgnoreOne => w.u8(10),
+        Instruction::Call(arity) => {
+            w.u8(11);
+            w.u8(arity.0)
+        }
+        Instruction::TailCall(arity) => {
+            w.u8(12);
+            w.u8(arity.0)
+        }
+        Instruction::Jump(d) => {
+            w.u8(13);
+            w.u32(d.0)
+        }
+        Instruction::CondJump(d) => {
+            w.u8(14);
+            w.u32(d.0)
+        }
+        Instruction::Ret => w.u8(15),
+        Instruction::AccessVariantField(constr_id, field) => {
+            w.u8(17);
+            w.id(*constr_id);
+            w.u8(field.0)
+        }
+        Instruction::Switch(constr_id, len) => {
+            w.u8(18);
+            w.id(*constr_id);
+            w.u8(len.0)
+        }
+        Instruction::MatchLiteral(lit_id, d) => {
+            w.u8(19);
+            w.id(*lit_id);
+            w.u32(d.0)
+        }
+        Instruction::MatchFailure(span) => {
+            w.u8(20);
+            w.span(span)
+        }
+        Instruction::MakeStruct(constr_id, nb_fields) => {
+            w.u8(21);
+            w.id(*constr_id);
+            w.u8(nb_fields.0)
+        }
+        Instruction::AccessFieldByName(field_id) => {
+            w.u8(22);
+            w.id(*field_id)
+        }
+        Instruction::PushHandler(d) => {
+            w.u8(23);
+            w.u32(d.0)
+        }
+        Instruction::PopHandler => w.u8(24),
+        Instruction::Throw => w.u8(25),
+    }
+}
+
+fn read_instruction(r: &mut Reader) -> Result<Instruction, DecodeError> {
+    let instruction = match r.u8()? {
+        0 => Instruction::PushLiteral(r.id::<LitId>()?),
+        1 => Instruction::FetchGlobal(r.id::<GlobalId>()?),
+        2 => Instruction::FetchNif(r.id::<NifId>()?),
+        3 => Instruction::FetchFun(r.id::<FunId>()?),
+        4 => Instruction::MakeClosure(r.id::<FunId>()?, CallArity(r.u8()?)),
+        5 => Instruction::FetchStackParam(ParamBindIndex(r.u8()?)),
+        6 => Instruction::FetchStackLocal(LocalBindIndex(r.u16()?)),
+        7 => Instruction::MakeList(ListLength(r.u32()?)),
+        8 => Instruction::AccessField(r.id::<ConstrId>()?, StructFieldIndex(r.u8()?)),
+        9 => Instruction::LocalBind(LocalBindIndex(r.u16()?)),
+        10 => Instruction::IgnoreOne,
+        11 => Instruction::Call(CallArity(r.u8()?)),
+        12 => Instruction::TailCall(CallArity(r.u8()?)),
+        13 => Instruction::Jump(InstructionDiff(r.u32()?)),
+        14 => Instruction::CondJump(InstructionDiff(r.u32()?)),
+        15 => Instruction::Ret,
+        16 => Instruction::MakeEnum(
+            r.id::<ConstrId>()?,
+            VariantIndex(r.u8()?),
+            CallArity(r.u8()?),
+        ),
+        17 => Instruction::AccessVariantField(r.id::<ConstrId>()?, StructFieldIndex(r.u8()?)),
+        18 => Instruction::Switch(r.id::<ConstrId>()?, SwitchLength(r.u8()?)),
+        19 => Instruction::MatchLiteral(r.id::<LitId>()?, InstructionDiff(r.u32()?)),
+        20 => Instruction::MatchFailure(r.span()?),
+        21 => Instruction::MakeStruct(r.id::<ConstrId>()?, CallArity(r.u8()?)),
+        22 => Instruction::AccessFieldByName(r.id::<FieldId>()?),
+        23 => Instruction::PushHandler(InstructionDiff(r.u32()?)),
+        24 => Instruction::PopHandler,
+        25 => Instruction::Throw,
+        tag => {
+            return Err(DecodeError::InvalidTag {
+                what: "instruction",
+                tag,
+            })
+        }
+    };
+    Ok(instruction)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{compile, CompilationError, CompilationParams, Environment};
+    use alloc::{boxed::Box, vec};
+    use werbolg_core::{
+        EnumDef, Expr, FunDef, Ident, Literal, MatchArm, Module, Namespace, Path, PathType,
+        Pattern, Privacy, Spanned, Statement, StructDef, Variable, Variant,
+    };
+
+    const CODEC: LiteralCodec<u64> = LiteralCodec {
+        encode: |lit, out| out.extend_from_slice(&lit.to_le_bytes()),
+        decode: |bytes| bytes.try_into().ok().map(u64::from_le_bytes),
+    };
+
+    fn literal_mapper(lit: Literal) -> Result<u64, CompilationError> {
+        match lit {
+            Literal::Number(n) => n
+                .parse()
+                .map_err(|_| CompilationError::LiteralNotSupported(Literal::Number(n))),
+            _ => Err(CompilationError::LiteralNotSupported(lit)),
+        }
+    }
+
+    fn spanned(ident: &str) -> Spanned<Ident> {
+        Spanned::new(0..0, Ident::from(ident))
+    }
+
+    fn local(ident: &str) -> Expr {
+        Expr::Path(0..0, Path::relative(Ident::from(ident)))
+    }
+
+    fn number(n: &str) -> Expr {
+        Expr::Literal(0..0, Literal::number(n))
+    }
+
+    fn call(exprs: Vec<Expr>) -> Expr {
+        Expr::Call(0..0, exprs)
+    }
+
+    fn function(name: Option<&str>, vars: &[&str], body: Expr) -> FunDef {
+        FunDef {
+            privacy: Privacy::Public,
+            name: name.map(Ident::from),
+            vars: vars.iter().map(|v| Variable(spanned(v))).collect(),
+            body,
+        }
+    }
+
+    /// Compile a module with an enumeration, a match, a closure and a recursive function counting to a number
+    fn unit(count_to: &str) -> CompilationUnit<u64> {
+        let shape = EnumDef {
+            name: spanned("shape"),
+            variants: vec![Variant(StructDef {
+                name: spanned("circle"),
+                fields: vec![spanned("r")],
+            })],
+        };
+        let circle = Path::new_raw(
+            PathType::Relative,
+            vec![Ident::from("shape"), Ident::from("circle")],
+        );
+        let pattern = Pattern::Constructor(
+            Spanned::new(0..0, circle.clone()),
+            vec![Spanned::new(0..0, Pattern::Bind(Ident::from("r")))],
+        );
+        let radius = Expr::Match {
+            span: 0..0,
+            expr: Box::new(local("s")),
+            arms: vec![MatchArm {
+                pattern: Spanned::new(0..0, pattern),
+                body: local("r"),
+            }],
+        };
+        let count = Expr::If {
+            span: 0..0,
+            cond: Box::new(Spanned::new(
+                0..0,
+                call(vec![local("eq"), local("n"), number("0")]),
+            )),
+            then_expr: Box::new(Spanned::new(0..0, local("acc"))),
+            else_expr: Box::new(Spanned::new(
+                0..0,
+                call(vec![
+                    local("count"),
+                    call(vec![local("-"), local("n"), number("1")]),
+                    call(vec![local("+"), local("acc"), number("1")]),
+                ]),
+            )),
+        };
+        let add_base = function(
+            None,
+            &["m"],
+            call(vec![local("+"), local("m"), local("base")]),
+        );
+        let main = Expr::List(
+            0..0,
+            vec![
+                call(vec![
+                    local("radius"),
+                    call(vec![Expr::Path(0..0, circle), number("4")]),
+                ]),
+                call(vec![
+                    Expr::Lambda(0..0, Box::new(add_base)),
+                    call(vec![local("count"), number(count_to), number("0")]),
+                ]),
+            ],
+        );
+        let module = Module {
+            statements: vec![
+                Statement::Enum(0..0, shape),
+                Statement::Function(0..0, function(Some("radius"), &["s"], radius)),
+                Statement::Function(0..0, function(Some("count"), &["n", "acc"], count)),
+                Statement::Function(0..0, function(Some("main"), &["base"], main)),
+            ],
+        };
+
+        let mut env = Environment::<(), ()>::new();
+        for nif in ["+", "-", "eq"] {
+            env.add_nif(&Namespace::root(), Ident::from(nif), ());
+        }
+        let params = CompilationParams { literal_mapper };
+        compile(&params, vec![(Namespace::root(), module)], &mut env).expect("compiled")
+    }
+
+    #[test]
+    fn unit_roundtrip() {
+        let bytes = unit("40").encode(&CODEC);
+        let decoded = CompilationUnit::decode(&bytes, &CODEC).expect("decoded");
+        assert_eq!(decoded.encode(&CODEC), bytes);
+
+        for len in 0..bytes.len() {
+            assert!(CompilationUnit::decode(&bytes[..len], &CODEC).is_err());
+        }
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+        assert_eq!(
+            CompilationUnit::decode(&trailing, &CODEC).err(),
+            Some(DecodeError::TrailingData(1))
+        );
+        let mut bad_version = bytes;
+        bad_version[4] = 0xff;
+        assert!(matches!(
+            CompilationUnit::decode(&bad_version, &CODEC),
+            Err(DecodeError::UnsupportedVersion(_))
+        ));
+    }
+
+    #[test]
+    fn unit_fingerprint() {
+        assert_eq!(unit("40").fingerprint(), unit("40").fingerprint());
+        assert_ne!(unit("40").fingerprint(), unit("41").fingerprint());
+
+        // the decoded unit has the fingerprint of the compiled one
+        let decoded = CompilationUnit::decode(&unit("40").encode(&CODEC), &CODEC).expect("decoded");
+        assert_eq!(decoded.fingerprint(), unit("40").fingerprint());
+    }
+}
@@ -1,10 +1,10 @@
-use alloc::vec::Vec;
+use alloc::{vec, vec::Vec};
 use core::hash::Hash;
 use core::marker::PhantomData;
 use hashbrown::HashMap;
 use werbolg_core::id::IdF;
 pub use werbolg_core::idvec::{IdVec, IdVecAfter};
-use werbolg_core::{Ident, Namespace, Path};
+use werbolg_core::{Ident, Namespace, Path, PathType};
 
 /// A simple lookup table from Ident to ID
 ///
@@ -147,23 +147,38 @@ impl<ID: IdF> SymbolsTable<ID> {
             if is_final {
                 return table.current.get(fragment);
             } else {
-                if let Some(child_table) = self.ns.get(fragment) {
-                    table = child_table;
-                } else {
-                    panic!("unknown namespace {:?} from path {:?}", fragment, path)
-                }
+                table = table.ns.get(fragment)?;
             }
         }
         return None;
     }
 
-    pub fn get(&self, _resolver: &NamespaceResolver, path: &Path) -> Option<ID> {
+    /// Get the ID associated with an absolute path
+    fn get_absolute(&self, path: &Path) -> Option<ID> {
         let (namespace, ident) = path.split();
-        if namespace.is_root() {
-            self.current.get(&ident)
-        } else {
-            let t = self.flat_table(&namespace);
-            t.and_then(|x| x.current.get(&ident))
+        self.flat_table(&namespace)
+            .and_then(|x| x.current.get(&ident))
+    }
+
+    /// Get the ID associated with the first candidate path of the resolver that exists in the table
+    pub fn get(&self, resolver: &NamespaceResolver, path: &Path) -> Option<ID> {
+        resolver
+            .candidates(path)
+            .iter()
+            .find_map(|candidate| self.get_absolute(candidate))
+    }
+
+    /// Iterate over the symbols defined directly in a namespace, or None if the namespace doesn't exist
+    pub fn namespace_symbols(&self, namespace: &Namespace) -> Option<impl Iterator<Item = &Ident>> {
+        self.flat_table(namespace)
+            .map(|table| table.current.iter().map(|(ident, _)| ident))
+    }
+
+    /// Keep only the symbols which ID satisfy the predicate, in all the namespaces
+    pub fn retain<F: Fn(ID) -> bool>(&mut self, f: &F) {
+        self.current.tbl.retain(|_, id| f(*id));
+        for child in self.ns.values_mut() {
+            child.retain(f)
         }
     }
 
@@ -258,11 +273,134 @@ impl<ID: IdF, T: Clone + Eq + Hash> UniqueTableBuilder<ID, T> {
 }
 
 /// Namespace Resolver
-pub struct NamespaceResolver;
+///
+/// Resolve the paths used in a namespace to the absolute paths of the symbols,
+/// using the namespace itself, the symbols it imports and then the root namespace
+#[derive(Clone, Debug)]
+pub struct NamespaceResolver {
+    current: Namespace,
+    imports: HashMap<Ident, Path>,
+}
 
 impl NamespaceResolver {
-    /// Create a empty namespace resolver
+    /// Create a empty namespace resolver, which resolve paths from the root namespace only
     pub fn none() -> Self {
-        Self
+        Self::new(Namespace::root())
+    }
+
+    /// Create a namespace resolver for the namespace in parameter, without any imports
+    pub fn new(current: Namespace) -> Self {
+        Self {
+            current,
+            imports: HashMap::new(),
+        }
+    }
+
+    /// The namespace which paths are resolved from
+    pub fn namespace(&self) -> &Namespace {
+        &self.current
+    }
+
+    /// Import the symbol at the absolute path, to be reachable by ident
+    ///
+    /// If a different symbol is already imported with the same ident, the path of this symbol is returned as error
+    pub fn import(&mut self, ident: Ident, path: Path) -> Result<(), Path> {
+        match self.imports.get(&ident) {
+            Some(existing) if *existing != path => Err(existing.clone()),
+            _ => {
+                self.imports.insert(ident, path);
+                Ok(())
+            }
+        }
+    }
+
+    /// Get all the absolute paths that a path could resolve to, in order of priority
+    ///
+    /// An absolute path only resolve to itself, whereas a relative path is tried in the current
+    /// namespace, then as an imported symbol, and finally from the root namespace
+    pub fn candidates(&self, path: &Path) -> Vec<Path> {
+        if path.path_type() == PathType::Absolute {
+            return vec![path.clone()];
+        }
+        let mut candidates = vec![self.current.path_with_path(path)];
+        let imported = path.get_local().and_then(|ident| self.imports.get(ident));
+        let root = Namespace::root().path_with_path(path);
+        for candidate in imported.into_iter().cloned().chain(core::iter::once(root)) {
+            if !candidates.contains(&candidate) {
+                candidates.push(candidate)
+            }
+        }
+        candidates
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use werbolg_core::FunId;
+
+    fn ident(s: &str) -> Ident {
+        Ident::from(s)
+    }
+
+    #[test]
+    fn resolver_candidates() {
+        let app = Namespace::root().append(ident("app"));
+        let math_double = Namespace::root()
+            .append(ident("math"))
+            .path_with_ident(&ident("double"));
+        let mut resolver = NamespaceResolver::new(app.clone());
+        resolver
+            .import(ident("twice"), math_double.clone())
+            .expect("imported");
+        assert_eq!(resolver.import(ident("twice"), math_double.clone()), Ok(()));
+        assert_eq!(
+            resolver.import(ident("twice"), Path::absolute(ident("other"))),
+            Err(math_double.clone())
+        );
+
+        // the current namespace, the import and then the root namespace
+        assert_eq!(
+            resolver.candidates(&Path::relative(ident("twice"))),
+            vec![
+                app.path_with_ident(&ident("twice")),
+                math_double,
+                Path::absolute(ident("twice")),
+            ]
+        );
+        assert_eq!(
+            resolver.candidates(&Path::absolute(ident("twice"))),
+            vec![Path::absolute(ident("twice"))]
+        );
+        // from the root namespace, the current namespace is the root one
+        assert_eq!(
+            NamespaceResolver::none().candidates(&Path::relative(ident("twice"))),
+            vec![Path::absolute(ident("twice"))]
+        );
+    }
+
+    #[test]
+    fn table_resolution() {
+        let app = Namespace::root().append(ident("app"));
+        let mut table = SymbolsTable::<FunId>::new();
+        table.create_namespace(app.clone()).expect("created");
+        let id = FunId::from_collection_len;
+        table.insert(&Namespace::root(), &Path::relative(ident("f")), id(0));
+        table.insert(&Namespace::root(), &Path::relative(ident("g")), id(1));
+        table.insert(&app, &Path::relative(ident("f")), id(2));
+
+        let mut resolver = NamespaceResolver::new(app);
+        resolver
+            .import(ident("h"), Path::absolute(ident("g")))
+            .expect("imported");
+        let get = |resolver: &NamespaceResolver, name: &str| {
+            table.get(resolver, &Path::relative(ident(name)))
+        };
+        assert_eq!(get(&resolver, "f"), Some(id(2)));
+        assert_eq!(get(&resolver, "g"), Some(id(1)));
+        assert_eq!(get(&resolver, "h"), Some(id(1)));
+        assert_eq!(get(&resolver, "i"), None);
+        assert_eq!(get(&NamespaceResolver::none(), "f"), Some(id(0)));
+        assert_eq!(get(&NamespaceResolver::none(), "h"), None);
     }
 }
@@ -77,10 +77,11 @@ define_id_remapper!(LitId, u32, 32, 'L');
 define_id_remapper!(ConstrId, u32, 32, 'C');
 define_id_remapper!(NifId, u32, 32, 'N');
 define_id_remapper!(GlobalId, u32, 32, 'G');
+define_id_remapper!(FieldId, u32, 32, 'I');
 define_id_remapper!(InstructionAddress, u32, 32, '%');
 
 /// A general function id (NifId or FunId)
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum ValueFun {
     /// NIF ID
     Native(NifId),
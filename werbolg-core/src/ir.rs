@@ -28,6 +28,7 @@ pub struct Module {
 /// * Use statement for namespace manipulation
 /// * Function definition
 /// * Struct definition
+/// * Enum definition
 /// * Naked expression
 #[derive(Clone, Debug)]
 pub enum Statement {
@@ -37,6 +38,8 @@ pub enum Statement {
     Function(Span, FunDef),
     /// Struct definition
     Struct(Span, StructDef),
+    /// Enum definition
+    Enum(Span, EnumDef),
     /// A naked Expression
     Expr(Expr),
 }
@@ -115,7 +118,7 @@ pub struct EnumDef {
 
 /// Define a variant for a enumeration
 #[derive(Clone, Debug)]
-pub struct Variant(StructDef);
+pub struct Variant(pub StructDef);
 
 /// A pattern "matching" for a let
 #[derive(Clone, Debug)]
@@ -142,6 +145,15 @@ pub enum Expr {
     /// either by disambiguating at the frontend level by adding explicit struct name
     /// or by other methods
     Field(Box<Expr>, Spanned<Path>, Spanned<Ident>),
+    /// Structure Field access by name only, e.g. `(some expr).$field`
+    ///
+    /// Contrary to `Field`, the structure doesn't need to be known by the frontend,
+    /// the field is found at runtime from the structure of the value
+    FieldByName(Box<Expr>, Spanned<Ident>),
+    /// Structure construction, e.g. `$struct-name { $field: $expr, ... }`
+    ///
+    /// The fields can be given in any order, but all the fields of the structure need to be given
+    Construct(Span, Spanned<Path>, Vec<(Spanned<Ident>, Expr)>),
     /// A List expression
     List(Span, Vec<Expr>),
     /// A Let binding of the form `let $binder = $expr in $expr`
@@ -161,6 +173,56 @@ pub enum Expr {
         /// Else expression, to run if the conditional does not hold
         else_expr: Box<Spanned<Expr>>,
     },
+    /// A Match expression `match $expr { $pattern => $expr, ... }`
+    ///
+    /// The arms are tried in order, and the first one whose pattern matches the value is run
+    Match {
+        /// Span of the match
+        span: Span,
+        /// The expression to match on
+        expr: Box<Expr>,
+        /// The arms of the match
+        arms: Vec<MatchArm>,
+    },
+    /// A Try expression `try $body catch $ident => $handler`
+    ///
+    /// When an error is thrown while running the body, including in the functions it calls,
+    /// the execution continues with the handler, with the error value bound to the ident
+    Try {
+        /// Span of the try
+        span: Span,
+        /// The expression to run
+        body: Box<Expr>,
+        /// The identifier bound to the error value in the handler
+        binder: Spanned<Ident>,
+        /// The expression to run when an error is thrown
+        handler: Box<Expr>,
+    },
+    /// A Throw expression `throw $expr`, throwing the value to the innermost try handler
+    Throw(Span, Box<Expr>),
+}
+
+/// A match arm of the form `$pattern => $expr`
+#[derive(Clone, Debug)]
+pub struct MatchArm {
+    /// Pattern that the value need to match for this arm to run
+    pub pattern: Spanned<Pattern>,
+    /// Expression to run, with the identifiers bound by the pattern
+    pub body: Expr,
+}
+
+/// A pattern to match a value against
+#[derive(Clone, Debug)]
+pub enum Pattern {
+    /// Wildcard pattern `_`, matching any value
+    Wildcard,
+    /// Binding pattern `$ident`, matching any value and binding it to the ident
+    Bind(Ident),
+    /// Literal pattern, matching a value equal to the literal
+    Literal(Literal),
+    /// Constructor pattern `$path($patterns)`, matching a structure or an enumeration variant,
+    /// and all its fields against the sub-patterns, in order
+    Constructor(Spanned<Path>, Vec<Spanned<Pattern>>),
 }
 
 /// A variable (function parameter)
@@ -10,8 +10,9 @@ pub mod id;
 pub mod idvec;
 pub mod ir;
 mod location;
+pub mod serialize;
 
 pub use basic::*;
-pub use id::{ConstrId, FunId, GlobalId, LitId, NifId, ValueFun};
+pub use id::{ConstrId, FieldId, FunId, GlobalId, LitId, NifId, ValueFun};
 pub use ir::*;
 pub use location::*;
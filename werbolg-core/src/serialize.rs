@@ -0,0 +1,837 @@
+//! Binary serialization
+//!
+//! Provides the primitives to write and read the werbolg binary formats, and the
+//! encoding of the IR [`Module`].
+//!
+//! Integers are written in little endian, and variable sized elements (strings, bytes, lists)
+//! are prefixed by their number of elements as a u32. Every format starts with a 4 bytes
+//! magic followed by the format version as a u16.
+
+use super::basic::*;
+use super::id::IdF;
+use super::idvec::IdVec;
+use super::ir::*;
+use super::location::*;
+use super::LitId;
+use alloc::{boxed::Box, string::String, vec::Vec};
+
+const IR_MAGIC: [u8; 4] = *b"WBIR";
+
+/// The version of the IR binary format written by the encoder
+pub const IR_FORMAT_VERSION: u16 = 1;
+
+/// Maximum nesting of recursive elements (e.g. expressions or namespaces) accepted by the decoders
+pub const MAX_NESTING: usize = 1024;
+
+/// Error when decoding binary data
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DecodeError {
+    /// The data doesn't start with the expected magic
+    InvalidMagic,
+    /// The data is in a format version not supported by this decoder
+    UnsupportedVersion(u16),
+    /// The data ends in the middle of an element
+    UnexpectedEnd,
+    /// Some data remains after the end of the decoded element
+    TrailingData(usize),
+    /// An unknown tag for an element
+    InvalidTag {
+        /// The kind of element decoded
+        what: &'static str,
+        /// The tag found
+        tag: u8,
+    },
+    /// A string is not valid UTF-8
+    InvalidUtf8,
+    /// A path without any component
+    EmptyPath,
+    /// The elements are nested above `MAX_NESTING`
+    TooDeep,
+    /// The literal codec refused the literal
+    InvalidLiteral(LitId),
+    /// A reference to an element that doesn't exist
+    InvalidReference {
+        /// The kind of element referenced
+        what: &'static str,
+        /// The index of the element referenced
+        index: u32,
+    },
+}
+
+/// Binary writer
+pub struct Writer(Vec<u8>);
+
+impl Writer {
+    /// Create a new writer, starting with the format magic and version
+    pub fn new(magic: &[u8; 4], version: u16) -> Self {
+        let mut w = Self(Vec::new());
+        w.0.extend_from_slice(magic);
+        w.u16(version);
+        w
+    }
+
+    /// Return the bytes written
+    pub fn finalize(self) -> Vec<u8> {
+        self.0
+    }
+
+    /// Write a byte
+    pub fn u8(&mut self, v: u8) {
+        self.0.push(v)
+    }
+
+    /// Write a u16
+    pub fn u16(&mut self, v: u16) {
+        self.0.extend_from_slice(&v.to_le_bytes())
+    }
+
+    /// Write a u32
+    pub fn u32(&mut self, v: u32) {
+        self.0.extend_from_slice(&v.to_le_bytes())
+    }
+
+    /// Write a u64
+    pub fn u64(&mut self, v: u64) {
+        self.0.extend_from_slice(&v.to_le_bytes())
+    }
+
+    /// Write the number of elements of a variable sized element
+    pub fn length(&mut self, len: usize) {
+        self.u32(len as u32)
+    }
+
+    /// Write bytes prefixed by their length
+    pub fn bytes(&mut self, bytes: &[u8]) {
+        self.length(bytes.len());
+        self.0.extend_from_slice(bytes)
+    }
+
+    /// Write a string
+    pub fn str(&mut self, s: &str) {
+        self.bytes(s.as_bytes())
+    }
+
+    /// Write an Id
+    pub fn id<ID: IdF>(&mut self, id: ID) {
+        self.u32(id.as_index() as u32)
+    }
+
+    /// Write an Ident
+    pub fn ident(&mut self, ident: &Ident) {
+        self.str(&ident.0)
+    }
+
+    /// Write a Span
+    pub fn span(&mut self, span: &Span) {
+        self.u64(span.start as u64);
+        self.u64(span.end as u64);
+    }
+
+    /// Write a Namespace, as the list of its components
+    pub fn namespace(&mut self, namespace: &Namespace) {
+        self.length(namespace.iter().count());
+        for ident in namespace.iter() {
+            self.ident(ident)
+        }
+    }
+
+    /// Write a list of elements prefixed by their number
+    pub fn list<'a, T: 'a, I, F>(&mut self, it: I, mut f: F)
+    where
+        I: ExactSizeIterator<Item = &'a T>,
+        F: FnMut(&mut Self, &'a T),
+    {
+        self.length(it.len());
+        for t in it {
+            f(self, t)
+        }
+    }
+}
+
+/// Binary reader
+pub struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    /// Create a new reader, checking that the data starts with the expected format magic and version
+    pub fn new(data: &'a [u8], magic: &[u8; 4], version: u16) -> Result<Self, DecodeError> {
+        let mut r = Self { data };
+        if r.take(magic.len())? != magic {
+            return Err(DecodeError::InvalidMagic);
+        }
+        let got = r.u16()?;
+        if got != version {
+            return Err(DecodeError::UnsupportedVersion(got));
+        }
+        Ok(r)
+    }
+
+    /// Check that all the data has been read
+    pub fn finalize(self) -> Result<(), DecodeError> {
+        if self.data.is_empty() {
+            Ok(())
+        } else {
+            Err(DecodeError::TrailingData(self.data.len()))
+        }
+    }
+
+    fn take(&mut self, n: usize) -> Result<&'a [u8], DecodeError> {
+        if self.data.len() < n {
+            return Err(DecodeError::UnexpectedEnd);
+        }
+        let (taken, rem) = self.data.split_at(n);
+        self.data = rem;
+        Ok(taken)
+    }
+
+    /// Read a byte
+    pub fn u8(&mut self) -> Result<u8, DecodeError> {
+        Ok(self.take(1)?[0])
+    }
+
+    /// Read a u16
+    pub fn u16(&mut self) -> Result<u16, DecodeError> {
+        let b = self.take(2)?;
+        Ok(u16::from_le_bytes([b[0], b[1]]))
+    }
+
+    /// Read a u32
+    pub fn u32(&mut self) -> Result<u32, DecodeError> {
+        let b = self.take(4)?;
+        Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    /// Read a u64
+    pub fn u64(&mut self) -> Result<u64, DecodeError> {
+        let mut b = [0u8; 8];
+        b.copy_from_slice(self.take(8)?);
+        Ok(u64::from_le_bytes(b))
+    }
+
+    /// Read the number of elements of a variable sized element
+    pub fn length(&mut self) -> Result<usize, DecodeError> {
+        self.u32().map(|v| v as usize)
+    }
+
+    /// Read bytes prefixed by their length
+    pub fn bytes(&mut self) -> Result<&'a [u8], DecodeError> {
+        let len = self.length()?;
+        self.take(len)
+    }
+
+    /// Read a string
+    pub fn str(&mut self) -> Result<&'a str, DecodeError> {
+        let bytes = self.bytes()?;
+        core::str::from_utf8(bytes).map_err(|_| DecodeError::InvalidUtf8)
+    }
+
+    /// Read an Id
+    pub fn id<ID: IdF>(&mut self) -> Result<ID, DecodeError> {
+        self.u32().map(|v| ID::from_collection_len(v as usize))
+    }
+
+    /// Read an Ident
+    pub fn ident(&mut self) -> Result<Ident, DecodeError> {
+        self.str().map(|s| Ident(String::from(s)))
+    }
+
+    /// Read a Span
+    pub fn span(&mut self) -> Result<Span, DecodeError> {
+        let start = self.u64()? as usize;
+        let end = self.u64()? as usize;
+        Ok(start..end)
+    }
+
+    /// Read a Namespace
+    pub fn namespace(&mut self) -> Result<Namespace, DecodeError> {
+        let nb_components = self.length()?;
+        let mut namespace = Namespace::root();
+        for _ in 0..nb_components {
+            namespace = namespace.append(self.ident()?);
+        }
+        Ok(namespace)
+    }
+
+    /// Read a list of elements prefixed by their number
+    pub fn list<T, F>(&mut self, mut f: F) -> Result<Vec<T>, DecodeError>
+    where
+        F: FnMut(&mut Self) -> Result<T, DecodeError>,
+    {
+        let len = self.length()?;
+        // the length is not trusted for preallocation, every element takes at least 1 byte
+        let mut v = Vec::new();
+        for _ in 0..len {
+            v.push(f(self)?);
+        }
+        Ok(v)
+    }
+
+    /// Read a list of elements prefixed by their number into an IdVec
+    pub fn idvec<ID: IdF, T, F>(&mut self, f: F) -> Result<IdVec<ID, T>, DecodeError>
+    where
+        F: FnMut(&mut Self) -> Result<T, DecodeError>,
+    {
+        let mut idvec = IdVec::new();
+        for t in self.list(f)? {
+            idvec.push(t);
+        }
+        Ok(idvec)
+    }
+}
+
+impl Module {
+    /// Encode this module to bytes
+    pub fn encode(&self) -> Vec<u8> {
+        let mut w = Writer::new(&IR_MAGIC, IR_FORMAT_VERSION);
+        w.list(self.statements.iter(), write_statement);
+        w.finalize()
+    }
+
+    /// Decode a module from bytes
+    pub fn decode(data: &[u8]) -> Result<Self, DecodeError> {
+        let mut r = Reader::new(data, &IR_MAGIC, IR_FORMAT_VERSION)?;
+        let statements = r.list(read_statement)?;
+        r.finalize()?;
+        Ok(Module { statements })
+    }
+}
+
+fn invalid_tag<T>(what: &'static str, tag: u8) -> Result<T, DecodeError> {
+    Err(DecodeError::InvalidTag { what, tag })
+}
+
+fn write_spanned_ident(w: &mut Writer, ident: &Spanned<Ident>) {
+    w.span(&ident.span);
+    w.ident(&ident.inner);
+}
+
+fn read_spanned_ident(r: &mut Reader) -> Result<Spanned<Ident>, DecodeError> {
+    let span = r.span()?;
+    Ok(Spanned::new(span, r.ident()?))
+}
+
+fn write_path(w: &mut Writer, path: &Path) {
+    w.u8(match path.path_type() {
+        PathType::Absolute => 0,
+        PathType::Relative => 1,
+    });
+    w.length(path.len().get());
+    for (_, ident) in path.components() {
+        w.ident(ident)
+    }
+}
+
+fn read_path(r: &mut Reader) -> Result<Path, DecodeError> {
+    let path_type = match r.u8()? {
+        0 => PathType::Absolute,
+        1 => PathType::Relative,
+        tag => return invalid_tag("path type", tag),
+    };
+    let idents = r.list(|r| r.ident())?;
+    if idents.is_empty() {
+        return Err(DecodeError::EmptyPath);
+    }
+    Ok(Path::new_raw(path_type, idents))
+}
+
+fn write_literal(w: &mut Writer, literal: &Literal) {
+    match literal {
+        Literal::Bool(s) => {
+            w.u8(0);
+            w.str(s)
+        }
+        Literal::String(s) => {
+            w.u8(1);
+            w.str(s)
+        }
+        Literal::Number(s) => {
+            w.u8(2);
+            w.str(s)
+        }
+        Literal::Decimal(s) => {
+            w.u8(3);
+            w.str(s)
+        }
+        Literal::Bytes(b) => {
+            w.u8(4);
+            w.bytes(b)
+        }
+    }
+}
+
+fn read_literal(r: &mut Reader) -> Result<Literal, DecodeError> {
+    match r.u8()? {
+        0 => Ok(Literal::Bool(r.str()?.into())),
+        1 => Ok(Literal::String(r.str()?.into())),
+        2 => Ok(Literal::Number(r.str()?.into())),
+        3 => Ok(Literal::Decimal(r.str()?.into())),
+        4 => Ok(Literal::Bytes(r.bytes()?.into())),
+        tag => invalid_tag("literal", tag),
+    }
+}
+
+fn write_statement(w: &mut Writer, stmt: &Statement) {
+    match stmt {
+        Statement::Use(u) => {
+            w.u8(0);
+            w.ident(&u.namespace);
+            w.list(u.hiding.iter(), |w, ident| w.ident(ident));
+            w.list(u.renames.iter(), |w, (from, to)| {
+                w.ident(from);
+                w.ident(to)
+            });
+        }
+        Statement::Function(span, fundef) => {
+            w.u8(1);
+            w.span(span);
+            write_fundef(w, fundef)
+        }
+        Statement::Struct(span, structdef) => {
+            w.u8(2);
+            w.span(span);
+            write_structdef(w, structdef)
+        }
+        Statement::Expr(expr) => {
+            w.u8(3);
+            write_expr(w, expr)
+        }
+        Statement::Enum(span, enumdef) => {
+            w.u8(4);
+            w.span(span);
+            write_spanned_ident(w, &enumdef.name);
+            w.list(enumdef.variants.iter(), |w, variant| {
+                write_structdef(w, &variant.0)
+            })
+        }
+    }
+}
+
+fn read_statement(r: &mut Reader) -> Result<Statement, DecodeError> {
+    match r.u8()? {
+        0 => Ok(Statement::Use(Use {
+            namespace: r.ident()?,
+            hiding: r.list(|r| r.ident())?,
+            renames: r.list(|r| Ok((r.ident()?, r.ident()?)))?,
+        })),
+        1 => {
+            let span = r.span()?;
+            Ok(Statement::Function(span, read_fundef(r, 0)?))
+        }
+        2 => {
+            let span = r.span()?;
+            Ok(Statement::Struct(span, read_structdef(r)?))
+        }
+        3 => Ok(Statement::Expr(read_expr(r, 0)?)),
+        4 => {
+            let span = r.span()?;
+            Ok(Statement::Enum(
+                span,
+                EnumDef {
+                    name: read_spanned_ident(r)?,
+                    variants: r.list(|r| read_structdef(r).map(Variant))?,
+                },
+            ))
+        }
+        tag => invalid_tag("statement", tag),
+    }
+}
+
+fn write_structdef(w: &mut Writer, structdef: &StructDef) {
+    write_spanned_ident(w, &structdef.name);
+    w.list(structdef.fields.iter(), write_spanned_ident);
+}
+
+fn read_structdef(r: &mut Reader) -> Result<StructDef, DecodeError> {
+    Ok(StructDef {
+        name: read_spanned_ident(r)?,
+        fields: r.list(read_spanned_ident)?,
+    })
+}
+
+fn write_fundef(w: &mut Writer, fundef: &FunDef) {
+    w.u8(match fundef.privacy {
+        Privacy::Public => 0,
+        Privacy::Private => 1,
+    });
+    match &fundef.name {
+        None => w.u8(0),
+        Some(name) => {
+            w.u8(1);
+            w.ident(name)
+        }
+    }
+    w.list(fundef.vars.iter(), |w, var| write_spanned_ident(w, &var.0));
+    write_expr(w, &fundef.body)
+}
+
+fn read_fundef(r: &mut Reader, depth: usize) -> Result<FunDef, DecodeError> {
+    let privacy = match r.u8()? {
+        0 => Privacy::Public,
+        1 => Privacy::Private,
+        tag => return invalid_tag("privacy", tag),
+    };
+    let name = match r.u8()? {
+        0 => None,
+        1 => Some(r.ident()?),
+        tag => return invalid_tag("function name", tag),
+    };
+    let vars = r.list(|r| read_spanned_ident(r).map(Variable))?;
+    let body = read_expr(r, depth + 1)?;
+    Ok(FunDef {
+        privacy,
+        name,
+        vars,
+        body,
+    })
+}
+
+fn write_spanned_expr(w: &mut Writer, expr: &Spanned<Expr>) {
+    w.span(&expr.span);
+    write_expr(w, &expr.inner)
+}
+
+fn read_spanned_expr(r: &mut Reader, depth: usize) -> Result<Box<Spanned<Expr>>, DecodeError> {
+    let span = r.span()?;
+    Ok(Box::new(Spanned::new(span, read_expr(r, depth)?)))
+}
+
+fn write_expr(w: &mut Writer, expr: &Expr) {
+    match expr {
+        Expr::Literal(span, literal) => {
+            w.u8(0);
+            w.span(span);
+            write_literal(w, literal)
+        }
+        Expr::Path(span, path) => {
+            w.u8(1);
+            w.span(span);
+            write_path(w, path)
+        }
+        Expr::Field(expr, struct_path, field) => {
+            w.u8(2);
+            write_expr(w, expr);
+            w.span(&struct_path.span);
+            write_path(w, &struct_path.inner);
+            write_spanned_ident(w, field)
+        }
+        Expr::List(span, exprs) => {
+            w.u8(3);
+            w.span(span);
+            w.list(exprs.iter(), write_expr)
+        }
+        Expr::Let(binder, body, in_expr) => {
+            w.u8(4);
+            match binder {
+                Binder::Unit => w.u8(0),
+                Binder::Ignore => w.u8(1),
+                Binder::Ident(ident) => {
+                    w.u8(2);
+                    w.ident(ident)
+                }
+            }
+            write_expr(w, body);
+            write_expr(w, in_expr)
+        }
+        Expr::Lambda(span, fundef) => {
+            w.u8(5);
+            w.span(span);
+            write_fundef(w, fundef)
+        }
+        Expr::Call(span, exprs) => {
+            w.u8(6);
+            w.span(span);
+            w.list(exprs.iter(), write_expr)
+        }
+        Expr::If {
+            span,
+            cond,
+            then_expr,
+            else_expr,
+        } => {
+            w.u8(7);
+            w.span(span);
+            write_spanned_expr(w, cond);
+            write_spanned_expr(w, then_expr);
+            write_spanned_expr(w, else_expr)
+        }
+        Expr::FieldByName(expr, field) => {
+            w.u8(10);
+            write_expr(w, expr);
+            write_spanned_ident(w, field)
+        }
+        Expr::Construct(span, struct_path, fields) => {
+            w.u8(9);
+            w.span(span);
+            w.span(&struct_path.span);
+            write_path(w, &struct_path.inner);
+            w.list(fields.iter(), |w, (field, expr)| {
+                write_spanned_ident(w, field);
+                write_expr(w, expr)
+            })
+        }
+        Expr::Match { span, expr, arms } => {
+            w.u8(8);
+            w.span(span);
+            write_expr(w, expr);
+            w.list(arms.iter(), |w, arm| {
+                w.span(&arm.pattern.span);
+                write_pattern(w, &arm.pattern.inner);
+                write_expr(w, &arm.body)
+            })
+        }
+        Expr::Try {
+            span,
+            body,
+            binder,
+            handler,
+        } => {
+            w.u8(11);
+            w.span(span);
+            write_expr(w, body);
+            write_spanned_ident(w, binder);
+            write_expr(w, handler)
+        }
+        Expr::Throw(span, expr) => {
+            w.u8(12);
+            w.span(span);
+            write_expr(w, expr)
+        }
+    }
+}
+
+fn read_expr(r: &mut Reader, depth: usize) -> Result<Expr, DecodeError> {
+    if depth > MAX_NESTING {
+        return Err(DecodeError::TooDeep);
+    }
+    let depth = depth + 1;
+    match r.u8()? {
+        0 => {
+            let span = r.span()?;
+            Ok(Expr::Literal(span, read_literal(r)?))
+        }
+        1 => {
+            let span = r.span()?;
+            Ok(Expr::Path(span, read_path(r)?))
+        }
+        2 => {
+            let expr = read_expr(r, depth)?;
+            let span = r.span()?;
+            let struct_path = Spanned::new(span, read_path(r)?);
+            let field = read_spanned_ident(r)?;
+            Ok(Expr::Field(Box::new(expr), struct_path, field))
+        }
+        3 => {
+            let span = r.span()?;
+            Ok(Expr::List(span, r.list(|r| read_expr(r, depth))?))
+        }
+        4 => {
+            let binder = match r.u8()? {
+                0 => Binder::Unit,
+                1 => Binder::Ignore,
+                2 => Binder::Ident(r.ident()?),
+                tag => return invalid_tag("binder", tag),
+            };
+            let body = read_expr(r, depth)?;
+            let in_expr = read_expr(r, depth)?;
+            Ok(Expr::Let(binder, Box::new(body), Box::new(in_expr)))
+        }
+        5 => {
+            let span = r.span()?;
+            Ok(Expr::Lambda(span, Box::new(read_fundef(r, depth)?)))
+        }
+        6 => {
+            let span = r.span()?;
+            Ok(Expr::Call(span, r.list(|r| read_expr(r, depth))?))
+        }
+        7 => Ok(Expr::If {
+            span: r.span()?,
+            cond: read_spanned_expr(r, depth)?,
+            then_expr: read_spanned_expr(r, depth)?,
+            else_expr: read_spanned_expr(r, depth)?,
+        }),
+        8 => Ok(Expr::Match {
+            span: r.span()?,
+            expr: Box::new(read_expr(r, depth)?),
+            arms: r.list(|r| {
+                let span = r.span()?;
+                Ok(MatchArm {
+                    pattern: Spanned::new(span, read_pattern(r, depth)?),
+                    body: read_expr(r, depth)?,
+                })
+            })?,
+        }),
+        9 => {
+            let span = r.span()?;
+            let path_span = r.span()?;
+            let struct_path = Spanned::new(path_span, read_path(r)?);
+            let fields = r.list(|r| Ok((read_spanned_ident(r)?, read_expr(r, depth)?)))?;
+            Ok(Expr::Construct(span, struct_path, fields))
+        }
+        10 => {
+            let expr = read_expr(r, depth)?;
+            let field = read_spanned_ident(r)?;
+            Ok(Expr::FieldByName(Box::new(expr), field))
+        }
+        11 => Ok(Expr::Try {
+            span: r.span()?,
+            body: Box::new(read_expr(r, depth)?),
+            binder: read_spanned_ident(r)?,
+            handler: Box::new(read_expr(r, depth)?),
+        }),
+        12 => {
+            let span = r.span()?;
+            Ok(Expr::Throw(span, Box::new(read_expr(r, depth)?)))
+        }
+        tag => invalid_tag("expression", tag),
+    }
+}
+
+fn write_pattern(w: &mut Writer, pattern: &Pattern) {
+    match pattern {
+        Pattern::Wildcard => w.u8(0),
+        Pattern::Bind(ident) => {
+            w.u8(1);
+            w.ident(ident)
+        }
+        Pattern::Literal(literal) => {
+            w.u8(2);
+            write_literal(w, literal)
+        }
+        Pattern::Constructor(path, patterns) => {
+            w.u8(3);
+            w.span(&path.span);
+            write_path(w, &path.inner);
+            w.list(patterns.iter(), |w, pattern| {
+                w.span(&pattern.span);
+                write_pattern(w, &pattern.inner)
+            })
+        }
+    }
+}
+
+fn read_pattern(r: &mut Reader, depth: usize) -> Result<Pattern, DecodeError> {
+    if depth > MAX_NESTING {
+        return Err(DecodeError::TooDeep);
+    }
+    match r.u8()? {
+        0 => Ok(Pattern::Wildcard),
+        1 => Ok(Pattern::Bind(r.ident()?)),
+        2 => Ok(Pattern::Literal(read_literal(r)?)),
+        3 => {
+            let span = r.span()?;
+            let path = Spanned::new(span, read_path(r)?);
+            let patterns = r.list(|r| {
+                let span = r.span()?;
+                Ok(Spanned::new(span, read_pattern(r, depth + 1)?))
+            })?;
+            Ok(Pattern::Constructor(path, patterns))
+        }
+        tag => invalid_tag("pattern", tag),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    fn spanned(ident: &str) -> Spanned<Ident> {
+        Spanned::new(0..0, Ident::from(ident))
+    }
+
+    fn local(ident: &str) -> Expr {
+        Expr::Path(0..0, Path::relative(Ident::from(ident)))
+    }
+
+    fn number(n: &str) -> Expr {
+        Expr::Literal(0..0, Literal::number(n))
+    }
+
+    /// A module with all the kinds of statements and most kinds of expressions
+    fn module() -> Module {
+        let shape = EnumDef {
+            name: spanned("shape"),
+            variants: vec![Variant(StructDef {
+                name: spanned("circle"),
+                fields: vec![spanned("r")],
+            })],
+        };
+        let circle = Path::new_raw(
+            PathType::Relative,
+            vec![Ident::from("shape"), Ident::from("circle")],
+        );
+        let pattern = Pattern::Constructor(
+            Spanned::new(2..8, circle),
+            vec![Spanned::new(4..5, Pattern::Bind(Ident::from("r")))],
+        );
+        let body = Expr::Match {
+            span: 1..20,
+            expr: Box::new(local("s")),
+            arms: vec![
+                MatchArm {
+                    pattern: Spanned::new(2..8, pattern),
+                    body: Expr::Call(9..12, vec![local("+"), local("r"), number("1")]),
+                },
+                MatchArm {
+                    pattern: Spanned::new(13..14, Pattern::Wildcard),
+                    body: Expr::List(15..20, vec![number("1"), number("2")]),
+                },
+            ],
+        };
+        Module {
+            statements: vec![
+                Statement::Use(Use {
+                    namespace: Ident::from("math"),
+                    hiding: vec![Ident::from("inc")],
+                    renames: vec![(Ident::from("double"), Ident::from("twice"))],
+                }),
+                Statement::Enum(0..30, shape),
+                Statement::Struct(
+                    31..40,
+                    StructDef {
+                        name: spanned("point"),
+                        fields: vec![spanned("x"), spanned("y")],
+                    },
+                ),
+                Statement::Function(
+                    41..60,
+                    FunDef {
+                        privacy: Privacy::Private,
+                        name: Some(Ident::from("describe")),
+                        vars: vec![Variable(spanned("s"))],
+                        body,
+                    },
+                ),
+                Statement::Expr(Expr::Let(
+                    Binder::Ident(Ident::from("x")),
+                    Box::new(number("3")),
+                    Box::new(local("x")),
+                )),
+            ],
+        }
+    }
+
+    #[test]
+    fn module_roundtrip() {
+        let bytes = module().encode();
+        let decoded = Module::decode(&bytes).expect("decoded");
+        assert_eq!(decoded.encode(), bytes);
+
+        for len in 0..bytes.len() {
+            assert!(Module::decode(&bytes[..len]).is_err());
+        }
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+        assert_eq!(
+            Module::decode(&trailing).err(),
+            Some(DecodeError::TrailingData(1))
+        );
+        let mut bad_version = bytes;
+        bad_version[4] = 0xff;
+        assert!(matches!(
+            Module::decode(&bad_version),
+            Err(DecodeError::UnsupportedVersion(_))
+        ));
+    }
+}
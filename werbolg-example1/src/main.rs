@@ -6,8 +6,8 @@ use value::{Value, HASHMAP_KIND};
 use werbolg_compile::{code_dump, compile, CompilationError, Environment, NamespaceResolver};
 use werbolg_core::{Ident, Literal, Namespace, Path};
 use werbolg_exec::{
-    ExecutionEnviron, ExecutionError, ExecutionMachine, ExecutionParams, NIFCall, Valuable,
-    WAllocator, NIF,
+    instruction_cost, ExecutionEnviron, ExecutionError, ExecutionMachine, ExecutionParams, NIFCall,
+    Valuable, WAllocator, NIF,
 };
 use werbolg_lang_common::FileUnit;
 
@@ -93,6 +93,14 @@ fn literal_to_value(lit: &MyLiteral) -> Value {
     }
 }
 
+fn literal_matches(lit: &MyLiteral, value: &Value) -> bool {
+    match (lit, value) {
+        (MyLiteral::Bool(b), Value::Bool(v)) => b == v,
+        (MyLiteral::Int(n), Value::Integral(v)) => n == v,
+        _ => false,
+    }
+}
+
 // only support bool and number from the werbolg core literal
 fn literal_mapper(lit: Literal) -> Result<MyLiteral, CompilationError> {
     match lit {
@@ -199,12 +207,33 @@ fn main() -> Result<(), ()> {
         )
         .expect("existing function as entry point");
 
-    let execution_params = ExecutionParams { literal_to_value };
+    let execution_params = ExecutionParams {
+        literal_to_value,
+        literal_matches,
+        instruction_cost,
+        max_call_depth: 1024,
+        max_stack_size: 1 << 20,
+        catchable: |error| matches!(error, ExecutionError::UserPanic { .. }).then_some(Value::Unit),
+    };
     let mut em = ExecutionMachine::new(&exec_module, &ee, execution_params, DummyAlloc, ());
 
     match werbolg_exec::exec(&mut em, entry_point, &[]) {
         Err(e) => {
-            println!("error: {:?} at {}", e, em.ip);
+            println!("error: {:?}", e);
+            for (i, frame) in em.stack_trace().iter().enumerate() {
+                let prefix = if i == 0 { "in" } else { "called from" };
+                match frame
+                    .location
+                    .as_ref()
+                    .and_then(|(_, span)| fileunit.report(span.clone()))
+                {
+                    None => println!("  {} {} at {}", prefix, frame, frame.ip),
+                    Some(report) => println!(
+                        "  {} {} at {}:{}:{}",
+                        prefix, frame, fileunit.filename, report.line, report.col
+                    ),
+                }
+            }
             return Err(());
         }
         Ok(val) => {
@@ -1,5 +1,6 @@
 use hashbrown::HashMap;
-use werbolg_core::{ConstrId, ValueFun};
+use werbolg_compile::VariantIndex;
+use werbolg_core::{ConstrId, FunId, ValueFun};
 use werbolg_exec::{ExecutionError, Valuable, ValueKind};
 
 #[derive(Clone, Debug)]
@@ -9,6 +10,10 @@ pub enum Value {
     Integral(u64),
     HashMap(HashMap<u32, u64>),
     Fun(ValueFun),
+    Closure(FunId, Vec<Value>),
+    List(Vec<Value>),
+    Struct(ConstrId, Vec<Value>),
+    Enum(ConstrId, VariantIndex, Vec<Value>),
 }
 
 impl Value {
@@ -19,6 +24,10 @@ impl Value {
             Value::HashMap(_) => HASHMAP_KIND,
             Value::Integral(_) => INT_KIND,
             Value::Fun(_) => FUN_KIND,
+            Value::Closure(_, _) => CLOSURE_KIND,
+            Value::List(_) => LIST_KIND,
+            Value::Struct(_, _) => STRUCT_KIND,
+            Value::Enum(_, _, _) => ENUM_KIND,
         }
     }
 }
@@ -28,6 +37,10 @@ pub const BOOL_KIND: ValueKind = b"    bool";
 pub const HASHMAP_KIND: ValueKind = b" hashmap";
 pub const INT_KIND: ValueKind = b"     int";
 pub const FUN_KIND: ValueKind = b"     fun";
+pub const CLOSURE_KIND: ValueKind = b" closure";
+pub const LIST_KIND: ValueKind = b"    list";
+pub const STRUCT_KIND: ValueKind = b"  struct";
+pub const ENUM_KIND: ValueKind = b"    enum";
 
 impl Valuable for Value {
     fn descriptor(&self) -> werbolg_exec::ValueKind {
@@ -48,18 +61,54 @@ impl Valuable for Value {
         }
     }
 
+    fn closure(&self) -> Option<(FunId, &[Self])> {
+        match self {
+            Self::Closure(fun, captured) => Some((*fun, captured)),
+            _ => None,
+        }
+    }
+
     fn structure(&self) -> Option<(ConstrId, &[Self])> {
-        None
+        match self {
+            Value::Struct(constr, fields) => Some((*constr, fields)),
+            _ => None,
+        }
+    }
+
+    fn enumeration(&self) -> Option<(ConstrId, VariantIndex, &[Self])> {
+        match self {
+            Value::Enum(constr, variant, fields) => Some((*constr, *variant, fields)),
+            _ => None,
+        }
     }
 
-    fn index(&self, _index: usize) -> Option<&Self> {
-        None
+    fn index(&self, index: usize) -> Option<&Self> {
+        match self {
+            Value::List(elements) => elements.get(index),
+            _ => None,
+        }
     }
 
     fn make_fun(fun: ValueFun) -> Self {
         Value::Fun(fun)
     }
 
+    fn make_closure(fun: FunId, captured: Vec<Self>) -> Self {
+        Value::Closure(fun, captured)
+    }
+
+    fn make_list(elements: Vec<Self>) -> Self {
+        Value::List(elements)
+    }
+
+    fn make_struct(constr: ConstrId, fields: Vec<Self>) -> Self {
+        Value::Struct(constr, fields)
+    }
+
+    fn make_enum(constr: ConstrId, variant: VariantIndex, fields: Vec<Self>) -> Self {
+        Value::Enum(constr, variant, fields)
+    }
+
     fn make_dummy() -> Self {
         Value::Unit
     }
@@ -1,5 +1,363 @@
+use crate::{ExecutionError, SnapshotError, Valuable};
+use alloc::{string::String, vec::Vec};
+use werbolg_compile::VariantIndex;
+use werbolg_core::{ConstrId, FunId};
+
 /// Werbolg Value allocator
+///
+/// All the composite values (lists, structures, enumerations and closures) created by the execution
+/// are created through the allocator, in two steps: their size is first accounted with [`WAllocator::allocate`],
+/// which can fail, and then the value is created. The execution accounts for a value before taking its inner
+/// values from the stack, so that an allocation failure leaves the execution as it was.
+/// NIFs creating composite values (e.g. strings with [`WAllocator::make_string`]) account for them the same way,
+/// with [`composite_size`] and [`string_size`].
+///
+/// The execution also accesses the inner values of the composite values through the allocator, so that
+/// an allocator can keep them itself and give the execution values referring to them (see [`crate::ArenaAllocator`]).
+///
+/// By default the values are created and accessed with [`Valuable`] and nothing is accounted.
 pub trait WAllocator {
     /// the type of value managed by this allocator
-    type Value;
+    type Value: Valuable;
+
+    /// Account for the allocation of a value of an estimated number of bytes, before creating it
+    fn allocate(&mut self, _bytes: usize) -> Result<(), ExecutionError> {
+        Ok(())
+    }
+
+    /// Start the accounting of a new execution, called when the execution machine is initialized
+    fn reset(&mut self) {}
+
+    /// Append the state of the allocator to a snapshot of the execution
+    ///
+    /// An AllocatorNotCaptured error is returned if the state can't be captured, e.g. if the
+    /// composite values are kept by the allocator itself
+    fn save_state(&self, _out: &mut Vec<u8>) -> Result<(), SnapshotError> {
+        Ok(())
+    }
+
+    /// Restore the state of the allocator saved by [`WAllocator::save_state`] in a snapshot of the execution
+    fn restore_state(&mut self, state: &[u8]) -> Result<(), SnapshotError> {
+        if state.is_empty() {
+            Ok(())
+        } else {
+            Err(SnapshotError::InvalidAllocatorState)
+        }
+    }
+
+    /// Create a list value from its elements
+    fn make_list(&mut self, elements: &[Self::Value]) -> Self::Value {
+        Self::Value::make_list(elements.to_vec())
+    }
+
+    /// Create a closure value, from a function and the values it captures
+    fn make_closure(&mut self, fun: FunId, captured: &[Self::Value]) -> Self::Value {
+        Self::Value::make_closure(fun, captured.to_vec())
+    }
+
+    /// Create a structure value, from the structure and its fields in order
+    fn make_struct(&mut self, constr: ConstrId, fields: &[Self::Value]) -> Self::Value {
+        Self::Value::make_struct(constr, fields.to_vec())
+    }
+
+    /// Create an enumeration value, from the enumeration, the variant and the fields of the variant
+    fn make_enum(
+        &mut self,
+        constr: ConstrId,
+        variant: VariantIndex,
+        fields: &[Self::Value],
+    ) -> Self::Value {
+        Self::Value::make_enum(constr, variant, fields.to_vec())
+    }
+
+    /// Create a string value, for the value types that can be created from a `String`
+    fn make_string(&mut self, content: String) -> Self::Value
+    where
+        Self::Value: From<String>,
+    {
+        Self::Value::from(content)
+    }
+
+    /// Get the function and the captured values of a closure value, or None if not a closure
+    fn closure<'a>(&'a self, value: &'a Self::Value) -> Option<(FunId, &'a [Self::Value])> {
+        value.closure()
+    }
+
+    /// Get the structure and the fields of a structure value, or None if not a structure
+    fn structure<'a>(&'a self, value: &'a Self::Value) -> Option<(ConstrId, &'a [Self::Value])> {
+        value.structure()
+    }
+
+    /// Get the enumeration, the variant and the fields of an enumeration value, or None if not an enumeration
+    fn enumeration<'a>(
+        &'a self,
+        value: &'a Self::Value,
+    ) -> Option<(ConstrId, VariantIndex, &'a [Self::Value])> {
+        value.enumeration()
+    }
+
+    /// Get the element #index of a value (e.g. the element of a list), or None if not valid
+    fn index<'a>(&'a self, value: &'a Self::Value, index: usize) -> Option<&'a Self::Value> {
+        value.index(index)
+    }
+}
+
+/// The estimated size of a composite value with N inner values
+pub fn composite_size<V>(nb_values: usize) -> usize {
+    core::mem::size_of::<V>() * (nb_values + 1)
+}
+
+/// The estimated size of a string value of N bytes
+pub fn string_size<V>(len: usize) -> usize {
+    core::mem::size_of::<V>() + len
+}
+
+/// Allocator accounting for the values created by an execution, and limiting them to a quota
+///
+/// The accounting is the total allocated by the current execution, values that are released are not deducted
+pub struct QuotaAllocator<V> {
+    /// The number of bytes allocated
+    pub bytes: usize,
+    /// The number of composite values allocated
+    pub objects: usize,
+    /// The maximum number of bytes that can be allocated
+    pub max_bytes: usize,
+    _value: core::marker::PhantomData<V>,
+}
+
+impl<V> QuotaAllocator<V> {
+    /// Create a new allocator, with nothing allocated yet, and the maximum number of bytes that can be allocated
+    pub fn new(max_bytes: usize) -> Self {
+        Self {
+            bytes: 0,
+            objects: 0,
+            max_bytes,
+            _value: core::marker::PhantomData,
+        }
+    }
+}
+
+impl<V: Valuable> WAllocator for QuotaAllocator<V> {
+    type Value = V;
+
+    fn allocate(&mut self, bytes: usize) -> Result<(), ExecutionError> {
+        let total = self.bytes.saturating_add(bytes);
+        if total > self.max_bytes {
+            return Err(ExecutionError::OutOfMemory {
+                allocated: self.bytes,
+                requested: bytes,
+            });
+        }
+        self.bytes = total;
+        self.objects += 1;
+        Ok(())
+    }
+
+    fn reset(&mut self) {
+        self.bytes = 0;
+        self.objects = 0;
+    }
+
+    fn save_state(&self, out: &mut Vec<u8>) -> Result<(), SnapshotError> {
+        out.extend_from_slice(&(self.bytes as u64).to_le_bytes());
+        out.extend_from_slice(&(self.objects as u64).to_le_bytes());
+        Ok(())
+    }
+
+    fn restore_state(&mut self, state: &[u8]) -> Result<(), SnapshotError> {
+        let (Some(bytes), Some(objects)) = (state.get(0..8), state.get(8..)) else {
+            return Err(SnapshotError::InvalidAllocatorState);
+        };
+        let read = |b: &[u8]| b.try_into().map(u64::from_le_bytes);
+        let (Ok(bytes), Ok(objects)) = (read(bytes), read(objects)) else {
+            return Err(SnapshotError::InvalidAllocatorState);
+        };
+        self.bytes = bytes as usize;
+        self.objects = objects as usize;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::{ArenaHandle, ArenaValuable};
+    use alloc::vec::Vec;
+    use werbolg_core::{id::IdF, ValueFun};
+
+    #[derive(Clone, Debug, PartialEq)]
+    pub(crate) enum Value {
+        Str(String),
+        Fun(ValueFun),
+        List(Vec<Value>),
+        Closure(FunId, Vec<Value>),
+        Struct(ConstrId, Vec<Value>),
+        Enum(ConstrId, VariantIndex, Vec<Value>),
+        Handle(ArenaHandle),
+    }
+
+    impl From<String> for Value {
+        fn from(s: String) -> Self {
+            Value::Str(s)
+        }
+    }
+
+    impl Valuable for Value {
+        fn descriptor(&self) -> crate::ValueKind {
+            match self {
+                Value::Str(_) => b"     str",
+                Value::Fun(_) => b"     fun",
+                Value::List(_) => b"    list",
+                Value::Closure(_, _) => b" closure",
+                Value::Struct(_, _) => b"  struct",
+                Value::Enum(_, _, _) => b"    enum",
+                Value::Handle(_) => b"  handle",
+            }
+        }
+
+        fn conditional(&self) -> Option<bool> {
+            None
+        }
+
+        fn fun(&self) -> Option<ValueFun> {
+            match self {
+                Value::Fun(fun) => Some(*fun),
+                _ => None,
+            }
+        }
+
+        fn closure(&self) -> Option<(FunId, &[Self])> {
+            match self {
+                Value::Closure(fun, captured) => Some((*fun, captured)),
+                _ => None,
+            }
+        }
+
+        fn structure(&self) -> Option<(ConstrId, &[Self])> {
+            match self {
+                Value::Struct(constr, fields) => Some((*constr, fields)),
+                _ => None,
+            }
+        }
+
+        fn enumeration(&self) -> Option<(ConstrId, VariantIndex, &[Self])> {
+            match self {
+                Value::Enum(constr, variant, fields) => Some((*constr, *variant, fields)),
+                _ => None,
+            }
+        }
+
+        fn index(&self, index: usize) -> Option<&Self> {
+            match self {
+                Value::List(elements) => elements.get(index),
+                _ => None,
+            }
+        }
+
+        fn make_fun(fun: ValueFun) -> Self {
+            Value::Fun(fun)
+        }
+
+        fn make_closure(fun: FunId, captured: Vec<Self>) -> Self {
+            Value::Closure(fun, captured)
+        }
+
+        fn make_list(elements: Vec<Self>) -> Self {
+            Value::List(elements)
+        }
+
+        fn make_struct(constr: ConstrId, fields: Vec<Self>) -> Self {
+            Value::Struct(constr, fields)
+        }
+
+        fn make_enum(constr: ConstrId, variant: VariantIndex, fields: Vec<Self>) -> Self {
+            Value::Enum(constr, variant, fields)
+        }
+
+        fn make_dummy() -> Self {
+            Value::List(Vec::new())
+        }
+    }
+
+    impl ArenaValuable for Value {
+        fn make_handle(handle: ArenaHandle) -> Self {
+            Value::Handle(handle)
+        }
+
+        fn handle(&self) -> Option<ArenaHandle> {
+            match self {
+                Value::Handle(handle) => Some(*handle),
+                _ => None,
+            }
+        }
+    }
+
+    #[test]
+    fn quota() {
+        let list_size = composite_size::<Value>(2);
+        let str_size = string_size::<Value>(5);
+        let mut allocator = QuotaAllocator::<Value>::new(list_size + str_size);
+
+        allocator.allocate(str_size).expect("within the quota");
+        let s = allocator.make_string(String::from("hello"));
+        allocator.allocate(list_size).expect("within the quota");
+        let list = allocator.make_list(&[s.clone(), s]);
+        assert_eq!(list.index(1), Some(&Value::Str(String::from("hello"))));
+        assert_eq!(
+            (allocator.bytes, allocator.objects),
+            (list_size + str_size, 2)
+        );
+
+        assert!(matches!(
+            allocator.allocate(1),
+            Err(ExecutionError::OutOfMemory { allocated, requested: 1 }) if allocated == list_size + str_size
+        ));
+        assert_eq!(allocator.objects, 2);
+
+        // the accounting is carried over by a snapshot
+        let mut state = Vec::new();
+        allocator.save_state(&mut state).expect("saved");
+        let mut restored = QuotaAllocator::<Value>::new(list_size + str_size);
+        restored.restore_state(&state).expect("restored");
+        assert_eq!(
+            (restored.bytes, restored.objects),
+            (list_size + str_size, 2)
+        );
+        assert!(matches!(
+            restored.restore_state(&state[1..]),
+            Err(SnapshotError::InvalidAllocatorState)
+        ));
+
+        allocator.reset();
+        assert_eq!((allocator.bytes, allocator.objects), (0, 0));
+        allocator
+            .allocate(list_size + str_size)
+            .expect("within the quota");
+    }
+
+    #[test]
+    fn default_constructors() {
+        let mut allocator = QuotaAllocator::<Value>::new(usize::MAX);
+        let fun = FunId::from_collection_len(3);
+        let constr = ConstrId::from_collection_len(1);
+        let s = allocator.make_string(String::from("hello"));
+
+        let closure = allocator.make_closure(fun, core::slice::from_ref(&s));
+        assert_eq!(
+            allocator.closure(&closure),
+            Some((fun, core::slice::from_ref(&s)))
+        );
+        let structure = allocator.make_struct(constr, &[s.clone(), s.clone()]);
+        assert_eq!(
+            allocator.structure(&structure).map(|(c, f)| (c, f.len())),
+            Some((constr, 2))
+        );
+        let enumeration = allocator.make_enum(constr, VariantIndex(1), core::slice::from_ref(&s));
+        assert_eq!(
+            allocator.enumeration(&enumeration),
+            Some((constr, VariantIndex(1), core::slice::from_ref(&s)))
+        );
+        assert_eq!(allocator.structure(&enumeration), None);
+        assert_eq!(allocator.index(&closure, 0), None);
+    }
 }
@@ -0,0 +1,242 @@
+//! Arena allocator of the composite values of an execution machine
+//!
+//! The composite values are allocated in a region owned by the allocator, and so by the execution
+//! machine: their inner values are stored one after the other in a single vector, and the value
+//! given to the execution is only a handle, the index of the composite value in the region.
+//! The whole region is freed in bulk when the machine is dropped, or explicitly with
+//! [`ArenaAllocator::clear`].
+//!
+//! A handle is only valid for the allocator that created it: each region has a generation,
+//! unique to the allocator and renewed when the region is cleared, and the handles of another
+//! generation are not resolved. So handles can't cross machines (e.g. as messages between the tasks
+//! of a scheduler), and the execution fails on them as on a value of the wrong kind. For the same reason,
+//! an execution using an arena allocator can't be snapshotted.
+//!
+//! The execution accesses the inner values of a composite value through the allocator, NIFs using
+//! the [`Valuable`] accessors directly on a handle don't see them.
+
+use crate::{SnapshotError, Valuable, WAllocator};
+use alloc::vec::Vec;
+use core::ops::Range;
+use core::sync::atomic::{AtomicU32, Ordering};
+use werbolg_compile::VariantIndex;
+use werbolg_core::{ConstrId, FunId};
+
+/// The next generation of arena region
+static NEXT_GENERATION: AtomicU32 = AtomicU32::new(0);
+
+fn next_generation() -> u32 {
+    NEXT_GENERATION.fetch_add(1, Ordering::Relaxed)
+}
+
+/// Handle of a composite value allocated in an [`ArenaAllocator`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ArenaHandle {
+    generation: u32,
+    index: u32,
+}
+
+/// A value that can refer to a composite value allocated in an [`ArenaAllocator`]
+pub trait ArenaValuable: Valuable {
+    /// Create a value referring to a composite value of the arena
+    fn make_handle(handle: ArenaHandle) -> Self;
+
+    /// Get the handle of the composite value of the arena this value refers to, or None if not a handle
+    fn handle(&self) -> Option<ArenaHandle>;
+}
+
+/// The kind of composite value allocated in the arena
+#[derive(Clone, Copy)]
+enum Composite {
+    List,
+    Closure(FunId),
+    Struct(ConstrId),
+    Enum(ConstrId, VariantIndex),
+}
+
+/// Allocator placing the composite values of an execution machine in a region freed in bulk
+pub struct ArenaAllocator<V> {
+    /// The generation of the region, given to its handles
+    generation: u32,
+    /// The composite values, with the range of their inner values
+    objects: Vec<(Composite, Range<usize>)>,
+    /// The inner values of all the composite values
+    values: Vec<V>,
+}
+
+impl<V> ArenaAllocator<V> {
+    /// Create a new allocator, with an empty region
+    pub fn new() -> Self {
+        Self {
+            generation: next_generation(),
+            objects: Vec::new(),
+            values: Vec::new(),
+        }
+    }
+
+    /// Get the number of composite values allocated in the region
+    pub fn len(&self) -> usize {
+        self.objects.len()
+    }
+
+    /// Check if the region has no composite values
+    pub fn is_empty(&self) -> bool {
+        self.objects.is_empty()
+    }
+
+    /// Free all the composite values of the region
+    ///
+    /// The handles created before are not valid anymore, and are not resolved by this allocator
+    pub fn clear(&mut self) {
+        self.generation = next_generation();
+        self.objects.clear();
+        self.values.clear();
+    }
+}
+
+impl<V> Default for ArenaAllocator<V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<V: ArenaValuable> ArenaAllocator<V> {
+    /// Allocate a composite value in the region, and get its handle as a value
+    fn push(&mut self, composite: Composite, inner: &[V]) -> V {
+        let start = self.values.len();
+        self.values.extend_from_slice(inner);
+        let handle = ArenaHandle {
+            generation: self.generation,
+            index: self.objects.len() as u32,
+        };
+        self.objects.push((composite, start..self.values.len()));
+        V::make_handle(handle)
+    }
+
+    /// Get the composite value a value refers to, and its inner values
+    ///
+    /// None is returned if the value is not a handle, and Some(None) if the handle is not valid for this region
+    #[allow(clippy::option_option)]
+    fn get<'a>(&'a self, value: &V) -> Option<Option<(Composite, &'a [V])>> {
+        let handle = value.handle()?;
+        if handle.generation != self.generation {
+            return Some(None);
+        }
+        Some(
+            self.objects
+                .get(handle.index as usize)
+                .map(|(composite, range)| (*composite, &self.values[range.clone()])),
+        )
+    }
+}
+
+impl<V: ArenaValuable> WAllocator for ArenaAllocator<V> {
+    type Value = V;
+
+    fn make_list(&mut self, elements: &[V]) -> V {
+        self.push(Composite::List, elements)
+    }
+
+    fn make_closure(&mut self, fun: FunId, captured: &[V]) -> V {
+        self.push(Composite::Closure(fun), captured)
+    }
+
+    fn make_struct(&mut self, constr: ConstrId, fields: &[V]) -> V {
+        self.push(Composite::Struct(constr), fields)
+    }
+
+    fn make_enum(&mut self, constr: ConstrId, variant: VariantIndex, fields: &[V]) -> V {
+        self.push(Composite::Enum(constr, variant), fields)
+    }
+
+    /// The values of the region are not part of the snapshot of an execution, and their handles
+    /// wouldn't be valid for the region restoring it, so the state is never captured
+    fn save_state(&self, _out: &mut Vec<u8>) -> Result<(), SnapshotError> {
+        Err(SnapshotError::AllocatorNotCaptured)
+    }
+
+    fn closure<'a>(&'a self, value: &'a V) -> Option<(FunId, &'a [V])> {
+        match self.get(value) {
+            Some(Some((Composite::Closure(fun), captured))) => Some((fun, captured)),
+            Some(_) => None,
+            None => value.closure(),
+        }
+    }
+
+    fn structure<'a>(&'a self, value: &'a V) -> Option<(ConstrId, &'a [V])> {
+        match self.get(value) {
+            Some(Some((Composite::Struct(constr), fields))) => Some((constr, fields)),
+            Some(_) => None,
+            None => value.structure(),
+        }
+    }
+
+    fn enumeration<'a>(&'a self, value: &'a V) -> Option<(ConstrId, VariantIndex, &'a [V])> {
+        match self.get(value) {
+            Some(Some((Composite::Enum(constr, variant), fields))) => {
+                Some((constr, variant, fields))
+            }
+            Some(_) => None,
+            None => value.enumeration(),
+        }
+    }
+
+    fn index<'a>(&'a self, value: &'a V, index: usize) -> Option<&'a V> {
+        match self.get(value) {
+            Some(Some((Composite::List, elements))) => elements.get(index),
+            Some(_) => None,
+            None => value.index(index),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::allocator::tests::Value;
+    use alloc::{string::String, vec};
+    use werbolg_core::id::IdF;
+
+    #[test]
+    fn region() {
+        let mut arena = ArenaAllocator::<Value>::new();
+        let fun = FunId::from_collection_len(2);
+        let constr = ConstrId::from_collection_len(1);
+        let s = arena.make_string(String::from("hello"));
+
+        // the composite values are handles, their inner values are kept by the region
+        let list = arena.make_list(&[s.clone(), s.clone()]);
+        let closure = arena.make_closure(fun, core::slice::from_ref(&list));
+        let enumeration = arena.make_enum(constr, VariantIndex(1), &[]);
+        assert!(matches!(list, Value::Handle(_)));
+        assert_eq!(arena.len(), 3);
+        assert_eq!(arena.index(&list, 1), Some(&s));
+        assert_eq!(
+            arena.closure(&closure),
+            Some((fun, core::slice::from_ref(&list)))
+        );
+        assert_eq!(
+            arena.enumeration(&enumeration),
+            Some((constr, VariantIndex(1), &[][..]))
+        );
+        assert_eq!(arena.structure(&enumeration), None);
+        // the values which are not handles are accessed directly
+        let direct = Value::List(vec![s.clone()]);
+        assert_eq!(arena.index(&direct, 0), Some(&s));
+
+        assert!(matches!(
+            arena.save_state(&mut Vec::new()),
+            Err(SnapshotError::AllocatorNotCaptured)
+        ));
+
+        // the handles of another region, or of a cleared region, are not resolved
+        let other = ArenaAllocator::<Value>::new();
+        assert_eq!(other.index(&list, 0), None);
+        arena.clear();
+        assert!(arena.is_empty());
+        assert_eq!(arena.index(&list, 0), None);
+        let new_list = arena.make_list(core::slice::from_ref(&s));
+        assert_ne!(new_list, list);
+        assert_eq!(arena.index(&new_list, 0), Some(&s));
+    }
+}
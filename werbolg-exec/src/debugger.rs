@@ -0,0 +1,202 @@
+//! Debugger on top of the step by step execution
+//!
+//! The debugger drives the execution machine with [`step`], stopping at breakpoints
+//! or when a stepping command is complete. The depth of the call frames (the `rets` of the
+//! machine) is used to step over and out of calls. Note that a call in tail position replaces
+//! the frame of its caller, so it stays at the same depth.
+
+use crate::allocator::WAllocator;
+use crate::exec::step;
+use crate::{ExecutionError, ExecutionHooks, ExecutionMachine, Valuable};
+use alloc::collections::BTreeSet;
+use werbolg_compile::{
+    CompilationUnit, InstructionAddress, LocalBindIndex, NamespaceResolver, ParamBindIndex,
+};
+use werbolg_core::{FunId, Path};
+
+/// The reason the debugger gave back the control
+#[derive(Debug)]
+pub enum DebugStop<V> {
+    /// The execution is about to execute the instruction of a breakpoint
+    Breakpoint(InstructionAddress),
+    /// The stepping command is complete
+    Step,
+    /// The execution finished with the value
+    Finished(V),
+}
+
+/// Debugger with a set of breakpoints
+#[derive(Debug, Clone, Default)]
+pub struct Debugger {
+    breakpoints: BTreeSet<InstructionAddress>,
+    /// The address the debugger last gave back the control at
+    stopped_at: Option<InstructionAddress>,
+}
+
+impl Debugger {
+    /// Create a new debugger without any breakpoints
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a breakpoint on the instruction at this address
+    pub fn add_breakpoint(&mut self, ia: InstructionAddress) {
+        self.breakpoints.insert(ia);
+    }
+
+    /// Add a breakpoint on the entry point of a public function, returning its address
+    ///
+    /// None is returned if the path is not a function of the module. The private functions and
+    /// the lambdas are not reachable by path, use [`Debugger::add_fun_breakpoint`] for them
+    pub fn add_function_breakpoint<L>(
+        &mut self,
+        module: &CompilationUnit<L>,
+        path: &Path,
+    ) -> Option<InstructionAddress> {
+        let fun_id = module.funs_tbl.get(&NamespaceResolver::none(), path)?;
+        self.add_fun_breakpoint(module, fun_id)
+    }
+
+    /// Add a breakpoint on the entry point of any function of the module, returning its address
+    ///
+    /// None is returned if the function is not part of the module
+    pub fn add_fun_breakpoint<L>(
+        &mut self,
+        module: &CompilationUnit<L>,
+        fun_id: FunId,
+    ) -> Option<InstructionAddress> {
+        let ia = module.funs.get(fun_id)?.code_pos;
+        self.add_breakpoint(ia);
+        Some(ia)
+    }
+
+    /// Remove the breakpoint at this address, returning if there was one
+    pub fn remove_breakpoint(&mut self, ia: InstructionAddress) -> bool {
+        self.breakpoints.remove(&ia)
+    }
+
+    /// Iterate over all the breakpoints addresses
+    pub fn breakpoints(&self) -> impl Iterator<Item = InstructionAddress> + '_ {
+        self.breakpoints.iter().copied()
+    }
+
+    /// Run the execution until a breakpoint is reached or the execution finishes
+    ///
+    /// A breakpoint on the current instruction is reported before executing it, except when
+    /// the debugger already stopped there, so that the execution can be continued after
+    /// stopping at a breakpoint
+    pub fn run<'m, 'e, A: WAllocator<Value = V>, L, T, V: Valuable, H: ExecutionHooks<V>>(
+        &mut self,
+        em: &mut ExecutionMachine<'m, 'e, A, L, T, V, H>,
+    ) -> Result<DebugStop<V>, ExecutionError> {
+        self.run_while(em, |_| true)
+    }
+
+    /// Execute one instruction, entering the function if this is a call
+    pub fn step_into<'m, 'e, A: WAllocator<Value = V>, L, T, V: Valuable, H: ExecutionHooks<V>>(
+        &mut self,
+        em: &mut ExecutionMachine<'m, 'e, A, L, T, V, H>,
+    ) -> Result<DebugStop<V>, ExecutionError> {
+        self.run_while(em, |_| false)
+    }
+
+    /// Execute one instruction, and if this is a call, run until the function returns
+    pub fn step_over<'m, 'e, A: WAllocator<Value = V>, L, T, V: Valuable, H: ExecutionHooks<V>>(
+        &mut self,
+        em: &mut ExecutionMachine<'m, 'e, A, L, T, V, H>,
+    ) -> Result<DebugStop<V>, ExecutionError> {
+        let depth = em.rets.len();
+        self.run_while(em, |em| em.rets.len() > depth)
+    }
+
+    /// Run until the current function returns to its caller
+    pub fn step_out<'m, 'e, A: WAllocator<Value = V>, L, T, V: Valuable, H: ExecutionHooks<V>>(
+        &mut self,
+        em: &mut ExecutionMachine<'m, 'e, A, L, T, V, H>,
+    ) -> Result<DebugStop<V>, ExecutionError> {
+        let depth = em.rets.len();
+        self.run_while(em, |em| em.rets.len() >= depth)
+    }
+
+    /// Execute one instruction, then continue while the condition holds and no breakpoint is reached
+    fn run_while<'m, 'e, A: WAllocator<Value = V>, L, T, V: Valuable, H: ExecutionHooks<V>, F>(
+        &mut self,
+        em: &mut ExecutionMachine<'m, 'e, A, L, T, V, H>,
+        cont: F,
+    ) -> Result<DebugStop<V>, ExecutionError>
+    where
+        F: Fn(&ExecutionMachine<'m, 'e, A, L, T, V, H>) -> bool,
+    {
+        if em.stack.is_empty() {
+            self.stopped_at = None;
+            return Err(ExecutionError::ExecutionFinished);
+        }
+        // resuming from the last stop executes its instruction, otherwise a breakpoint
+        // on the current instruction (e.g. the entry point) is reported first
+        let resuming = self.stopped_at.take() == Some(em.ip);
+        if !resuming && self.breakpoints.contains(&em.ip) {
+            self.stopped_at = Some(em.ip);
+            return Ok(DebugStop::Breakpoint(em.ip));
+        }
+        loop {
+            if let Some(value) = step(em)? {
+                return Ok(DebugStop::Finished(value));
+            }
+            if self.breakpoints.contains(&em.ip) {
+                self.stopped_at = Some(em.ip);
+                return Ok(DebugStop::Breakpoint(em.ip));
+            }
+            if !cont(em) {
+                self.stopped_at = Some(em.ip);
+                return Ok(DebugStop::Step);
+            }
+        }
+    }
+}
+
+/// A view on the current call frame of the execution
+#[derive(Debug)]
+pub struct Frame<'a, V> {
+    /// The function being executed in this frame
+    pub fun: Option<FunId>,
+    /// The instruction about to be executed
+    pub ip: InstructionAddress,
+    /// The parameters of the function in the order they were given, followed by the captured values for a closure
+    pub params: &'a [V],
+    /// The local bound values, indexed by LocalBindIndex
+    pub locals: &'a [V],
+    /// The values pushed on the stack by the function, from the bottom to the top
+    pub operands: &'a [V],
+}
+
+impl<'a, V> Frame<'a, V> {
+    /// Get a parameter as fetched by the `FetchStackParam` instruction
+    pub fn param(&self, param: ParamBindIndex) -> Option<&'a V> {
+        let index = self.params.len().checked_sub(1 + param.0 as usize)?;
+        self.params.get(index)
+    }
+
+    /// Get a local bound value as fetched by the `FetchStackLocal` instruction
+    pub fn local(&self, local: LocalBindIndex) -> Option<&'a V> {
+        self.locals.get(local.0 as usize)
+    }
+}
+
+impl<'m, 'e, A, L, T, V: Valuable, H> ExecutionMachine<'m, 'e, A, L, T, V, H> {
+    /// Inspect the current call frame of the execution
+    ///
+    /// This is only meaningful when the execution is in progress, e.g. stopped by the debugger
+    pub fn current_frame(&self) -> Frame<'_, V> {
+        let values = &self.stack.values;
+        let sp = self.sp.0.min(values.len());
+        let base = self.frame_base().0.min(sp);
+        let locals_end = (sp + self.current_stack_size.0 as usize).min(values.len());
+        Frame {
+            fun: self.module.function_at(self.ip),
+            ip: self.ip,
+            params: &values[(base + 1).min(sp)..sp],
+            locals: &values[sp..locals_end],
+            operands: &values[locals_end..],
+        }
+    }
+}
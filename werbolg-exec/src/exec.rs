@@ -1,33 +1,42 @@
-use crate::Valuable;
+use crate::{ExecutionHooks, Valuable};
 
-use super::allocator::WAllocator;
+use super::allocator::{composite_size, WAllocator};
 use super::{ExecutionError, ExecutionMachine};
-use werbolg_compile::{CallArity, Instruction, InstructionAddress, LocalStackSize};
+use werbolg_compile::{CallArity, ConstrDef, Instruction, InstructionAddress, LocalStackSize};
 use werbolg_core as ir;
+use werbolg_core::id::IdF;
 use werbolg_core::ValueFun;
 
 /// Native Implemented Function
-pub struct NIF<'m, 'e, A, L, T, V> {
+pub struct NIF<'m, 'e, A, L, T, V, H = ()> {
     /// name of the NIF
     pub name: &'static str,
     /// the call itself
-    pub call: NIFCall<'m, 'e, A, L, T, V>,
+    pub call: NIFCall<'m, 'e, A, L, T, V, H>,
 }
 
 /// 2 Variants of Native calls
 ///
 /// * "Pure" function that don't have access to the execution machine
 /// * "Mut" function that have access to the execution machine and have more power / responsability.
-pub enum NIFCall<'m, 'e, A, L, T, V> {
+pub enum NIFCall<'m, 'e, A, L, T, V, H = ()> {
     /// "Pure" NIF call only takes the input parameter and return an output
     Pure(fn(&[V]) -> Result<V, ExecutionError>),
     /// "Raw" NIF takes the execution machine in parameter and return an output
-    Raw(fn(&mut ExecutionMachine<'m, 'e, A, L, T, V>) -> Result<V, ExecutionError>),
+    Raw(fn(&mut ExecutionMachine<'m, 'e, A, L, T, V, H>) -> Result<V, ExecutionError>),
 }
 
 /// Execute the module, calling function identified by FunId, with the arguments in parameters.
-pub fn exec<'module, 'environ, A: WAllocator<Value = V>, L, T, V: Valuable>(
-    em: &mut ExecutionMachine<'module, 'environ, A, L, T, V>,
+pub fn exec<
+    'module,
+    'environ,
+    A: WAllocator<Value = V>,
+    L,
+    T,
+    V: Valuable,
+    H: ExecutionHooks<V>,
+>(
+    em: &mut ExecutionMachine<'module, 'environ, A, L, T, V, H>,
     call: ir::FunId,
     args: &[V],
 ) -> Result<V, ExecutionError> {
@@ -41,10 +50,40 @@ pub fn exec<'module, 'environ, A: WAllocator<Value = V>, L, T, V: Valuable>(
 
 /// Initialize the execution machine with a call to the specified function (by FunId)
 /// and the arguments to this function as values
-pub fn initialize<'module, 'environ, A: WAllocator<Value = V>, L, T, V: Valuable>(
-    em: &mut ExecutionMachine<'module, 'environ, A, L, T, V>,
+pub fn initialize<
+    'module,
+    'environ,
+    A: WAllocator<Value = V>,
+    L,
+    T,
+    V: Valuable,
+    H: ExecutionHooks<V>,
+>(
+    em: &mut ExecutionMachine<'module, 'environ, A, L, T, V, H>,
     call: ir::FunId,
     args: &[V],
+) -> Result<Option<V>, ExecutionError> {
+    em.allocator.reset();
+    initialize_call(em, V::make_fun(ValueFun::Fun(call)), args)
+}
+
+/// Initialize the execution machine with a call to a function value (a function, a NIF or a closure)
+/// and the arguments to this function as values
+///
+/// Contrary to [`initialize`], the allocator is not reset, as the function value may have been
+/// allocated for this execution already
+pub(crate) fn initialize_call<
+    'module,
+    'environ,
+    A: WAllocator<Value = V>,
+    L,
+    T,
+    V: Valuable,
+    H: ExecutionHooks<V>,
+>(
+    em: &mut ExecutionMachine<'module, 'environ, A, L, T, V, H>,
+    call: V,
+    args: &[V],
 ) -> Result<Option<V>, ExecutionError> {
     let arity = args
         .len()
@@ -52,10 +91,15 @@ pub fn initialize<'module, 'environ, A: WAllocator<Value = V>, L, T, V: Valuable
         .map(CallArity)
         .map_err(|_| ExecutionError::ArityOverflow { got: args.len() })?;
 
-    em.stack.push_call(V::make_fun(ValueFun::Fun(call)), args);
+    // the initial call frame is at the bottom of the stack
+    em.rets.clear();
+    em.suspended = None;
+    em.handlers.clear();
+    em.stack.truncate(0);
+    em.stack.push_call(call, args);
 
     match process_call(em, arity)? {
-        CallResult::Jump(ip, local) => {
+        CallResult::Jump(ip, local, _) => {
             em.ip_set(ip);
             em.sp_set(local);
         }
@@ -67,17 +111,17 @@ pub fn initialize<'module, 'environ, A: WAllocator<Value = V>, L, T, V: Valuable
 /// Resume execution
 ///
 /// If the stack is empty (if the program is terminated already), then it returns an ExecutionFinished error
-pub fn exec_continue<'m, 'e, A: WAllocator<Value = V>, L, T, V: Valuable>(
-    em: &mut ExecutionMachine<'m, 'e, A, L, T, V>,
+pub fn exec_continue<'m, 'e, A: WAllocator<Value = V>, L, T, V: Valuable, H: ExecutionHooks<V>>(
+    em: &mut ExecutionMachine<'m, 'e, A, L, T, V, H>,
 ) -> Result<V, ExecutionError> {
-    if em.rets.is_empty() {
+    if em.stack.is_empty() {
         return Err(ExecutionError::ExecutionFinished);
     }
     exec_loop(em)
 }
 
-fn exec_loop<'m, 'e, A: WAllocator<Value = V>, L, T, V: Valuable>(
-    em: &mut ExecutionMachine<'m, 'e, A, L, T, V>,
+fn exec_loop<'m, 'e, A: WAllocator<Value = V>, L, T, V: Valuable, H: ExecutionHooks<V>>(
+    em: &mut ExecutionMachine<'m, 'e, A, L, T, V, H>,
 ) -> Result<V, ExecutionError> {
     loop {
         match step(em)? {
@@ -94,11 +138,37 @@ type StepResult<V> = Result<Option<V>, ExecutionError>;
 /// * an execution error
 /// * not an error : Either no value or a value if the execution of the program is finished
 ///
-/// The step function need to update the execution IP
-pub fn step<'m, 'e, A: WAllocator<Value = V>, L, T, V: Valuable>(
-    em: &mut ExecutionMachine<'m, 'e, A, L, T, V>,
+/// The step function need to update the execution IP. When the execution is suspended on a NIF call,
+/// this returns a NotReady error without doing anything, until the call is resumed with a value.
+///
+/// An error that is catchable according to the execution params is caught by the innermost
+/// try handler, if any, and the execution continues in the handler
+pub fn step<'m, 'e, A: WAllocator<Value = V>, L, T, V: Valuable, H: ExecutionHooks<V>>(
+    em: &mut ExecutionMachine<'m, 'e, A, L, T, V, H>,
 ) -> StepResult<V> {
+    match step_instruction(em) {
+        // the execution is resumed from where it stopped for those
+        Err(e @ (ExecutionError::NotReady | ExecutionError::OutOfFuel { .. })) => Err(e),
+        Err(e) if !em.handlers.is_empty() => match (em.params.catchable)(&e) {
+            None => Err(e),
+            Some(value) => {
+                let _ = em.unwind(value);
+                Ok(None)
+            }
+        },
+        result => result,
+    }
+}
+
+fn step_instruction<'m, 'e, A: WAllocator<Value = V>, L, T, V: Valuable, H: ExecutionHooks<V>>(
+    em: &mut ExecutionMachine<'m, 'e, A, L, T, V, H>,
+) -> StepResult<V> {
+    if em.suspended.is_some() {
+        return Err(ExecutionError::NotReady);
+    }
     let instr = &em.module.code[em.ip];
+    em.fuel_consume(instr)?;
+    em.hooks.on_instruction(em.ip, instr);
     match instr {
         Instruction::PushLiteral(lit) => {
             let literal = &em.module.lits[*lit];
@@ -117,6 +187,15 @@ pub fn step<'m, 'e, A: WAllocator<Value = V>, L, T, V: Valuable>(
             em.stack.push_value(V::make_fun(ValueFun::Fun(*fun_id)));
             em.ip_next();
         }
+        Instruction::MakeClosure(fun_id, captures) => {
+            em.allocator
+                .allocate(composite_size::<V>(captures.0 as usize))?;
+            let captured = em.stack.top_values(captures.0 as usize);
+            let closure = em.allocator.make_closure(*fun_id, captured);
+            em.stack.pop_values(captures.0 as usize);
+            em.stack.push_value(closure);
+            em.ip_next();
+        }
         Instruction::FetchStackLocal(local_bind) => {
             em.sp_push_value_from_local(*local_bind);
             em.ip_next()
@@ -125,9 +204,35 @@ pub fn step<'m, 'e, A: WAllocator<Value = V>, L, T, V: Valuable>(
             em.sp_push_value_from_param(*param_bind);
             em.ip_next()
         }
+        Instruction::MakeList(len) => {
+            em.allocator.allocate(composite_size::<V>(len.0 as usize))?;
+            let elements = em.stack.top_values(len.0 as usize);
+            let list = em.allocator.make_list(elements);
+            em.stack.pop_values(len.0 as usize);
+            em.stack.push_value(list);
+            em.ip_next();
+        }
+        Instruction::MakeStruct(constr, nb_fields) => {
+            em.allocator
+                .allocate(composite_size::<V>(nb_fields.0 as usize))?;
+            let fields = em.stack.top_values(nb_fields.0 as usize);
+            let structure = em.allocator.make_struct(*constr, fields);
+            em.stack.pop_values(nb_fields.0 as usize);
+            em.stack.push_value(structure);
+            em.ip_next();
+        }
+        Instruction::MakeEnum(constr, variant, nb_fields) => {
+            em.allocator
+                .allocate(composite_size::<V>(nb_fields.0 as usize))?;
+            let fields = em.stack.top_values(nb_fields.0 as usize);
+            let enumeration = em.allocator.make_enum(*constr, *variant, fields);
+            em.stack.pop_values(nb_fields.0 as usize);
+            em.stack.push_value(enumeration);
+            em.ip_next();
+        }
         Instruction::AccessField(expected_cid, idx) => {
             let val = em.stack.pop_value();
-            let Some((got_cid, inner)) = val.structure() else {
+            let Some((got_cid, inner)) = em.allocator.structure(&val) else {
                 return Err(ExecutionError::ValueNotStruct {
                     value_is: val.descriptor(),
                 });
@@ -149,6 +254,59 @@ pub fn step<'m, 'e, A: WAllocator<Value = V>, L, T, V: Valuable>(
             em.stack.push_value(inner[idx.0 as usize].clone());
             em.ip_next()
         }
+        Instruction::AccessFieldByName(field_id) => {
+            let val = em.stack.pop_value();
+            let Some((got_cid, inner)) = em.allocator.structure(&val) else {
+                return Err(ExecutionError::ValueNotStruct {
+                    value_is: val.descriptor(),
+                });
+            };
+
+            let field = &em.module.fields[*field_id];
+            let index = match em.module.constrs.vecdata.get(got_cid) {
+                Some(ConstrDef::Struct(struct_def)) => struct_def.find_field_index(field),
+                _ => None,
+            };
+            let Some(idx) = index else {
+                return Err(ExecutionError::StructFieldNotFound {
+                    constr: got_cid,
+                    field: field.clone(),
+                });
+            };
+            if idx.0 as usize >= inner.len() {
+                return Err(ExecutionError::StructFieldOutOfBound {
+                    constr: got_cid,
+                    field_index: idx,
+                    struct_len: inner.len(),
+                });
+            }
+            em.stack.push_value(inner[idx.0 as usize].clone());
+            em.ip_next()
+        }
+        Instruction::AccessVariantField(expected_cid, idx) => {
+            let val = em.stack.pop_value();
+            let Some((got_cid, _, inner)) = em.allocator.enumeration(&val) else {
+                return Err(ExecutionError::ValueNotEnum {
+                    value_is: val.descriptor(),
+                });
+            };
+
+            if got_cid != *expected_cid {
+                return Err(ExecutionError::StructMismatch {
+                    constr_expected: *expected_cid,
+                    constr_got: got_cid,
+                });
+            }
+            if idx.0 as usize >= inner.len() {
+                return Err(ExecutionError::StructFieldOutOfBound {
+                    constr: got_cid,
+                    field_index: *idx,
+                    struct_len: inner.len(),
+                });
+            }
+            em.stack.push_value(inner[idx.0 as usize].clone());
+            em.ip_next()
+        }
         Instruction::LocalBind(local_bind) => {
             let val = em.stack.pop_value();
             em.sp_set_value_at(*local_bind, val);
@@ -161,9 +319,12 @@ pub fn step<'m, 'e, A: WAllocator<Value = V>, L, T, V: Valuable>(
         Instruction::Call(arity) => {
             let val = process_call(em, *arity)?;
             match val {
-                CallResult::Jump(fun_ip, local_stack_size) => {
+                CallResult::Jump(fun_ip, local_stack_size, call_arity) => {
+                    if em.rets.len() >= em.params.max_call_depth {
+                        return Err(em.stack_overflow());
+                    }
                     em.rets
-                        .push((em.ip.next(), em.sp, local_stack_size, *arity));
+                        .push((em.ip.next(), em.sp, em.current_stack_size, call_arity));
                     em.sp_set(local_stack_size);
                     em.ip_set(fun_ip);
                 }
@@ -174,6 +335,20 @@ pub fn step<'m, 'e, A: WAllocator<Value = V>, L, T, V: Valuable>(
                 }
             }
         }
+        Instruction::TailCall(arity) => {
+            let val = process_call(em, *arity)?;
+            match val {
+                CallResult::Jump(fun_ip, local_stack_size, call_arity) => {
+                    em.frame_replace(call_arity, local_stack_size);
+                    em.ip_set(fun_ip);
+                }
+                CallResult::Value(nif_val) => {
+                    em.stack.pop_call(*arity);
+                    em.stack.push_value(nif_val);
+                    em.ip_next()
+                }
+            }
+        }
         Instruction::Jump(d) => em.ip_jump(*d),
         Instruction::CondJump(d) => {
             let val = em.stack.pop_value();
@@ -188,10 +363,57 @@ pub fn step<'m, 'e, A: WAllocator<Value = V>, L, T, V: Valuable>(
                 em.ip_jump(*d)
             }
         }
+        Instruction::Switch(cid, len) => {
+            let val = em.stack.pop_value();
+            // any value not of the constructor goes to the last entry of the jump table
+            let entry = match (em.allocator.enumeration(&val), em.allocator.structure(&val)) {
+                (Some((got_cid, variant, _)), _) if got_cid == *cid && variant.0 < len.0 => {
+                    variant.0
+                }
+                (_, Some((got_cid, _))) if got_cid == *cid => 0,
+                _ => len.0,
+            };
+            em.ip_set(InstructionAddress::from_collection_len(
+                em.ip.as_index() + 1 + entry as usize,
+            ));
+        }
+        Instruction::MatchLiteral(lit, d) => {
+            let val = em.stack.pop_value();
+            if (em.params.literal_matches)(&em.module.lits[*lit], &val) {
+                em.ip_next()
+            } else {
+                em.ip_jump(*d)
+            }
+        }
+        Instruction::MatchFailure(span) => {
+            return Err(ExecutionError::MatchFailure { span: span.clone() })
+        }
+        Instruction::PushHandler(d) => {
+            let mut handler_ip = em.ip.next();
+            handler_ip += *d;
+            em.handler_push(handler_ip);
+            em.ip_next()
+        }
+        Instruction::PopHandler => {
+            em.handlers.pop();
+            em.ip_next()
+        }
+        Instruction::Throw => {
+            let val = em.stack.pop_value();
+            if let Err(val) = em.unwind(val) {
+                let value_is = val.descriptor();
+                em.stack.push_value(val);
+                return Err(ExecutionError::UncaughtThrow { value_is });
+            }
+        }
         Instruction::Ret => {
             let val = em.stack.pop_value();
+            em.hooks.on_return(&val);
             match em.rets.pop() {
-                None => return Ok(Some(val)),
+                None => {
+                    em.stack.truncate(0);
+                    return Ok(Some(val));
+                }
                 Some((ret, sp, stack_size, arity)) => {
                     em.sp_unlocal(em.current_stack_size);
                     em.current_stack_size = stack_size;
@@ -203,36 +425,71 @@ pub fn step<'m, 'e, A: WAllocator<Value = V>, L, T, V: Valuable>(
             }
         }
     }
+    if em.stack.values.len() > em.params.max_stack_size {
+        return Err(em.stack_overflow());
+    }
     //println!("IP={} SP={} STACK={}", em.ip, em.sp.0, em.stack2.top().0);
     Ok(None)
 }
 
+/// Default fuel cost of an instruction
+///
+/// Calls and value constructions cost more than the other instructions,
+/// as they do more work and can allocate
+pub fn instruction_cost(instr: &Instruction) -> u64 {
+    match instr {
+        Instruction::Call(_) | Instruction::TailCall(_) => 10,
+        Instruction::MakeClosure(_, _)
+        | Instruction::MakeList(_)
+        | Instruction::MakeEnum(_, _, _)
+        | Instruction::MakeStruct(_, _) => 5,
+        Instruction::AccessFieldByName(_) | Instruction::Switch(_, _) => 2,
+        _ => 1,
+    }
+}
+
 enum CallResult<V> {
-    Jump(InstructionAddress, LocalStackSize),
+    /// Jump to the function code, with its local stack size and the arity of the call
+    /// on the stack, which include the captured values for closures
+    Jump(InstructionAddress, LocalStackSize, CallArity),
     Value(V),
 }
 
-fn process_call<'m, 'e, A: WAllocator, L, T, V: Valuable>(
-    em: &mut ExecutionMachine<'m, 'e, A, L, T, V>,
+fn process_call<'m, 'e, A: WAllocator<Value = V>, L, T, V: Valuable, H: ExecutionHooks<V>>(
+    em: &mut ExecutionMachine<'m, 'e, A, L, T, V, H>,
     arity: CallArity,
 ) -> Result<CallResult<V>, ExecutionError> {
     let first = em.stack.get_call(arity);
-    let Some(fun) = first.fun() else {
+    let (fun, captured) = if let Some(fun) = first.fun() {
+        (fun, None)
+    } else if let Some((fun_id, captured)) = em.allocator.closure(first) {
+        (ValueFun::Fun(fun_id), Some(captured.to_vec()))
+    } else {
         //em.debug_state();
         return Err(ExecutionError::CallingNotFunc {
             value_is: first.descriptor(),
         });
     };
 
+    let (_first, args) = em.stack.get_call_and_args(arity);
+    em.hooks.on_call(fun, args);
+
     match fun {
         ValueFun::Native(nifid) => {
+            em.hooks.on_nif(nifid);
             let res = match &em.environ.nifs[nifid].call {
                 NIFCall::Pure(nif) => {
                     let (_first, args) = em.stack.get_call_and_args(arity);
-                    nif(args)?
+                    nif(args)
                 }
-                NIFCall::Raw(nif) => nif(em)?,
+                NIFCall::Raw(nif) => nif(em),
             };
+            if let Err(ExecutionError::NotReady) = res {
+                // the call stays on the stack, until the execution is resumed with its value
+                em.suspended = Some(arity);
+            }
+            let res = res?;
+            em.hooks.on_return(&res);
             Ok(CallResult::Value(res))
         }
         ValueFun::Fun(funid) => {
@@ -243,7 +500,24 @@ fn process_call<'m, 'e, A: WAllocator, L, T, V: Valuable>(
                     got: arity,
                 });
             }
-            Ok(CallResult::Jump(call_def.code_pos, call_def.stack_size))
+            let (code_pos, stack_size) = (call_def.code_pos, call_def.stack_size);
+
+            // captured values are given to the function after the call arguments
+            let call_arity = match captured {
+                None => arity,
+                Some(captured) => {
+                    let got = arity.0 as usize + captured.len();
+                    let call_arity = got
+                        .try_into()
+                        .map(CallArity)
+                        .map_err(|_| ExecutionError::ArityOverflow { got })?;
+                    for value in captured {
+                        em.stack.push_value(value);
+                    }
+                    call_arity
+                }
+            };
+            Ok(CallResult::Jump(code_pos, stack_size, call_arity))
         }
     }
 }
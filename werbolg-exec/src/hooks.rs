@@ -0,0 +1,37 @@
+//! Observation of the execution
+//!
+//! The hooks are a field of the execution machine, and called by [`step`](crate::step)
+//! as the execution progresses. All the hooks do nothing by default, so when unused (with `()`, the default
+//! hooks of the machine) they are compiled away.
+
+use werbolg_compile::{Instruction, InstructionAddress};
+use werbolg_core::{NifId, ValueFun};
+
+/// Callbacks on the events of the execution
+pub trait ExecutionHooks<V> {
+    /// Called before executing the instruction at this address
+    fn on_instruction(&mut self, _ip: InstructionAddress, _instruction: &Instruction) {}
+
+    /// Called when a function is called, with the arguments of the call
+    ///
+    /// The arguments don't include the values captured by a closure
+    fn on_call(&mut self, _fun: ValueFun, _args: &[V]) {}
+
+    /// Called when a NIF is called, after `on_call`
+    fn on_nif(&mut self, _nif: NifId) {}
+
+    /// Called when a function or a NIF returns a value
+    ///
+    /// A function called in tail position returns for its caller too, so there is
+    /// only one return for both calls. A NIF that suspended the execution returns when
+    /// the execution is resumed with a value
+    fn on_return(&mut self, _value: &V) {}
+
+    /// Called when an error or a thrown value is caught by a try handler, with the number of
+    /// call frames (`rets`) kept by the unwinding
+    ///
+    /// The functions and NIFs of the discarded frames don't return
+    fn on_catch(&mut self, _depth: usize) {}
+}
+
+impl<V> ExecutionHooks<V> for () {}
@@ -4,38 +4,52 @@
 
 extern crate alloc;
 
-use ir::{ConstrId, GlobalId, NifId};
+use ir::{ConstrId, FunId, GlobalId, NifId, Span};
 use werbolg_compile::{
     CallArity, LocalBindIndex, LocalStackSize, ParamBindIndex, StructFieldIndex,
 };
-use werbolg_compile::{CompilationUnit, InstructionAddress, InstructionDiff};
+use werbolg_compile::{CompilationUnit, Instruction, InstructionAddress, InstructionDiff};
 use werbolg_core as ir;
 use werbolg_core::idvec::IdVec;
 
 mod allocator;
+mod arena;
+mod debugger;
 mod exec;
+mod hooks;
+mod profiler;
+mod scheduler;
+mod snapshot;
 mod valuable;
 
-use alloc::{string::String, vec::Vec};
-pub use allocator::WAllocator;
+use alloc::{boxed::Box, string::String, vec::Vec};
+pub use allocator::{composite_size, string_size, QuotaAllocator, WAllocator};
+pub use arena::{ArenaAllocator, ArenaHandle, ArenaValuable};
+pub use debugger::{DebugStop, Debugger, Frame};
+pub use hooks::ExecutionHooks;
+pub use profiler::{ProfileCounters, ProfileFrame, Profiler};
+pub use scheduler::{
+    NewMachine, Scheduler, SchedulerNifs, SchedulerParams, TaskEvent, TaskId, TaskState,
+};
+pub use snapshot::{SnapshotError, ValueCodec, SNAPSHOT_FORMAT_VERSION};
 pub use valuable::{Valuable, ValueKind};
 
-pub use exec::{exec, exec_continue, step, NIFCall, NIF};
+pub use exec::{exec, exec_continue, initialize, instruction_cost, step, NIFCall, NIF};
 
 /// Execution environment with index Nifs by their NifId, and global variable with their GlobalId
-pub struct ExecutionEnviron<'m, 'e, A, L, T, V> {
+pub struct ExecutionEnviron<'m, 'e, A, L, T, V, H = ()> {
     /// Indexed NIFs
-    pub nifs: IdVec<NifId, NIF<'m, 'e, A, L, T, V>>,
+    pub nifs: IdVec<NifId, NIF<'m, 'e, A, L, T, V, H>>,
     /// Indexed Globals
     pub globals: IdVec<GlobalId, V>,
 }
 
-impl<'m, 'e, A, L, T, V> ExecutionEnviron<'m, 'e, A, L, T, V> {
+impl<'m, 'e, A, L, T, V, H> ExecutionEnviron<'m, 'e, A, L, T, V, H> {
     /// Pack a streamlined compilation environment into an execution environment
     ///
     /// this is the result of calling `Environment::finalize()`
     pub fn from_compile_environment(
-        tuple: (IdVec<GlobalId, V>, IdVec<NifId, NIF<'m, 'e, A, L, T, V>>),
+        tuple: (IdVec<GlobalId, V>, IdVec<NifId, NIF<'m, 'e, A, L, T, V, H>>),
     ) -> Self {
         Self {
             nifs: tuple.1,
@@ -49,12 +63,25 @@ impl<'m, 'e, A, L, T, V> ExecutionEnviron<'m, 'e, A, L, T, V> {
 pub struct ExecutionParams<L, V> {
     /// function to map from compilation L literal to a user chosen V value type
     pub literal_to_value: fn(&L) -> V,
+    /// function to check if a value is equal to a compilation L literal, for the literal patterns
+    pub literal_matches: fn(&L, &V) -> bool,
+    /// function giving the fuel cost of executing an instruction, see [`instruction_cost`] for a default
+    pub instruction_cost: fn(&Instruction) -> u64,
+    /// maximum number of call frames, not counting the initial call
+    pub max_call_depth: usize,
+    /// maximum number of values on the value stack
+    pub max_stack_size: usize,
+    /// function giving the value of a catchable error for the try handlers, or None if the error
+    /// is not catchable and stops the execution
+    ///
+    /// The errors suspending the execution (NotReady and OutOfFuel) are never caught
+    pub catchable: fn(&ExecutionError) -> Option<V>,
 }
 
 /// Execution machine
-pub struct ExecutionMachine<'m, 'e, A, L, T, V> {
+pub struct ExecutionMachine<'m, 'e, A, L, T, V, H = ()> {
     /// Environ
-    pub environ: &'e ExecutionEnviron<'m, 'e, A, L, T, V>,
+    pub environ: &'e ExecutionEnviron<'m, 'e, A, L, T, V, H>,
     /// Module
     pub module: &'m CompilationUnit<L>,
     /// call frame return values
@@ -73,6 +100,51 @@ pub struct ExecutionMachine<'m, 'e, A, L, T, V> {
     pub allocator: A,
     /// User controlled data
     pub userdata: T,
+    /// Callbacks on the events of the execution, `()` when the execution is not observed
+    pub hooks: H,
+    /// Remaining fuel, or None if the execution is not metered
+    pub fuel: Option<u64>,
+    /// The arity of the NIF call the execution is suspended on, waiting to be resumed with a value
+    pub suspended: Option<CallArity>,
+    /// The registered try handlers, from the outermost to the innermost
+    pub handlers: Vec<Handler>,
+}
+
+/// A try handler registered by the execution, with the state to restore when an error is caught
+#[derive(Debug, Clone)]
+pub struct Handler {
+    /// The address of the handler code
+    pub ip: InstructionAddress,
+    /// The number of call frames (`rets`) when the handler was registered
+    pub depth: usize,
+    /// The stack pointer of the frame of the handler
+    pub sp: StackPointer,
+    /// The local stack size of the frame of the handler
+    pub stack_size: LocalStackSize,
+    /// The number of values on the stack when the handler was registered
+    pub stack_len: usize,
+}
+
+/// A call frame of the execution, as reported in a stack trace
+#[derive(Debug, Clone)]
+pub struct StackFrame {
+    /// The function executing in this frame
+    pub fun: FunId,
+    /// The name of the function, None for anonymous function
+    pub name: Option<ir::Ident>,
+    /// The instruction being executed in this frame, which is the call instruction for the callers
+    pub ip: InstructionAddress,
+    /// The source location (namespace and span) of the instruction, if recorded by the compiler
+    pub location: Option<(ir::Namespace, Span)>,
+}
+
+impl core::fmt::Display for StackFrame {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match &self.name {
+            Some(name) => write!(f, "{}", name.0),
+            None => write!(f, "anonymous function {:?}", self.fun),
+        }
+    }
 }
 
 /// Execution Stack pointer
@@ -82,7 +154,7 @@ pub struct ExecutionMachine<'m, 'e, A, L, T, V> {
 /// * over it:
 ///   * the local stack for bounded value for this function
 ///   * then finally stack based (push/pop) values
-#[derive(Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
 pub struct StackPointer(usize);
 
 /// Value stack
@@ -114,6 +186,18 @@ impl<V: Valuable> ValueStack<V> {
         }
     }
 
+    /// Move the call on the top of the stack (the function value and its arguments) down to `base`,
+    /// discarding all the values in between
+    pub fn shift_call(&mut self, base: StackPointer, arity: CallArity) {
+        let call_start = self.values.len() - (arity.0 as usize) - 1;
+        self.values.drain(base.0..call_start);
+    }
+
+    /// Check if the stack has no values
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
     /// Truncate the stack to n elements
     pub fn truncate(&mut self, n: usize) {
         self.values.truncate(n)
@@ -146,6 +230,18 @@ impl<V: Valuable> ValueStack<V> {
         self.values.pop().expect("can be popped")
     }
 
+    /// Get the N values from the top of the stack, in the order they were pushed
+    pub fn top_values(&self, n: usize) -> &[V] {
+        let top = self.values.len();
+        &self.values[top - n..]
+    }
+
+    /// Pop the N values from the top of the stack
+    pub fn pop_values(&mut self, n: usize) {
+        let top = self.values.len();
+        self.values.truncate(top - n)
+    }
+
     /// Get the call value and associated arguments
     pub fn get_call_and_args(&self, arity: CallArity) -> (&V, &[V]) {
         let top = self.values.len();
@@ -172,6 +268,20 @@ impl<'m, 'e, A, L, T, V: Valuable> ExecutionMachine<'m, 'e, A, L, T, V> {
         params: ExecutionParams<L, V>,
         allocator: A,
         userdata: T,
+    ) -> Self {
+        Self::with_hooks(module, environ, params, allocator, userdata, ())
+    }
+}
+
+impl<'m, 'e, A, L, T, V: Valuable, H> ExecutionMachine<'m, 'e, A, L, T, V, H> {
+    /// Create a new execution machine, with hooks called on the events of the execution
+    pub fn with_hooks(
+        module: &'m CompilationUnit<L>,
+        environ: &'e ExecutionEnviron<'m, 'e, A, L, T, V, H>,
+        params: ExecutionParams<L, V>,
+        allocator: A,
+        userdata: T,
+        hooks: H,
     ) -> Self {
         Self {
             environ,
@@ -179,14 +289,132 @@ impl<'m, 'e, A, L, T, V: Valuable> ExecutionMachine<'m, 'e, A, L, T, V> {
             stack: ValueStack::new(),
             rets: Vec::new(),
             userdata,
+            hooks,
             allocator,
             ip: InstructionAddress::default(),
             sp: StackPointer::default(),
             params,
             current_stack_size: LocalStackSize(0),
+            fuel: None,
+            suspended: None,
+            handlers: Vec::new(),
+        }
+    }
+
+    /// Get the call frames of the execution, from the current function to the initial call
+    ///
+    /// Functions that were called in tail position replaced the frame of their caller,
+    /// so the caller doesn't appear in the trace
+    pub fn stack_trace(&self) -> Vec<StackFrame> {
+        let callers = self.rets.iter().rev().map(|(ret, _, _, _)| ret.prev());
+        core::iter::once(self.ip)
+            .chain(callers)
+            .filter_map(|ip| {
+                let fun = self.module.function_at(ip)?;
+                let location = self
+                    .module
+                    .location_of(ip)
+                    .map(|(namespace, span)| (namespace.clone(), span.clone()));
+                Some(StackFrame {
+                    fun,
+                    name: self.module.funs[fun].name.clone(),
+                    ip,
+                    location,
+                })
+            })
+            .collect()
+    }
+
+    /// Create a stack overflow error for the current execution
+    fn stack_overflow(&self) -> ExecutionError {
+        ExecutionError::StackOverflow {
+            depth: self.rets.len(),
+            trace: self.stack_trace(),
+        }
+    }
+
+    /// Attach the current stack trace of the execution to an error
+    pub fn traced(&self, error: ExecutionError) -> ExecutionError {
+        ExecutionError::Traced {
+            error: Box::new(error),
+            trace: self.stack_trace(),
+        }
+    }
+
+    /// Resume a suspended execution, using the value as the result of the pending NIF call
+    ///
+    /// The call is removed from the stack and replaced by the value, and the execution
+    /// can carry on after the call instruction with `exec_continue`
+    pub fn resume_with(&mut self, value: V) -> Result<(), ExecutionError>
+    where
+        H: ExecutionHooks<V>,
+    {
+        let Some(arity) = self.suspended.take() else {
+            return Err(ExecutionError::NotSuspended);
+        };
+        self.hooks.on_return(&value);
+        self.stack.pop_call(arity);
+        self.stack.push_value(value);
+        self.ip_next();
+        Ok(())
+    }
+
+    /// Register a try handler in the current frame, with its code at this address
+    fn handler_push(&mut self, ip: InstructionAddress) {
+        self.handlers.push(Handler {
+            ip,
+            depth: self.rets.len(),
+            sp: self.sp,
+            stack_size: self.current_stack_size,
+            stack_len: self.stack.values.len(),
+        });
+    }
+
+    /// Unwind the execution to the innermost handler, which continues with the error value
+    ///
+    /// The value is given back if there is no handler
+    fn unwind(&mut self, value: V) -> Result<(), V>
+    where
+        H: ExecutionHooks<V>,
+    {
+        let Some(handler) = self.handlers.pop() else {
+            return Err(value);
+        };
+        self.hooks.on_catch(handler.depth);
+        self.rets.truncate(handler.depth);
+        self.stack.truncate(handler.stack_len);
+        self.sp = handler.sp;
+        self.current_stack_size = handler.stack_size;
+        self.stack.push_value(value);
+        self.ip_set(handler.ip);
+        Ok(())
+    }
+
+    /// Set the fuel available to the execution, or None to not meter the execution
+    pub fn set_fuel(&mut self, fuel: Option<u64>) {
+        self.fuel = fuel;
+    }
+
+    /// Add fuel to a metered execution, typically after an [`ExecutionError::OutOfFuel`]
+    pub fn add_fuel(&mut self, fuel: u64) {
+        if let Some(remaining) = &mut self.fuel {
+            *remaining = remaining.saturating_add(fuel);
         }
     }
 
+    /// Consume the fuel for the execution of an instruction
+    #[inline]
+    fn fuel_consume(&mut self, instr: &Instruction) -> Result<(), ExecutionError> {
+        if let Some(remaining) = self.fuel {
+            let cost = (self.params.instruction_cost)(instr);
+            if remaining < cost {
+                return Err(ExecutionError::OutOfFuel { needed: cost });
+            }
+            self.fuel = Some(remaining - cost);
+        }
+        Ok(())
+    }
+
     /// increment the instruction pointer
     #[inline]
     pub fn ip_next(&mut self) {
@@ -242,6 +470,29 @@ impl<'m, 'e, A, L, T, V: Valuable> ExecutionMachine<'m, 'e, A, L, T, V> {
         self.stack.get_and_push(index);
     }
 
+    /// Get the stack index where the current call frame starts (the function value), which is
+    /// the bottom of the stack for the initial call
+    #[inline]
+    fn frame_base(&self) -> StackPointer {
+        match self.rets.last() {
+            None => StackPointer(0),
+            Some((_, _, _, arity)) => StackPointer(self.sp.0 - arity.0 as usize - 1),
+        }
+    }
+
+    /// Replace the current call frame by the call on the top of the stack
+    ///
+    /// The caller of the current function becomes the caller of the new call
+    #[inline]
+    fn frame_replace(&mut self, call_arity: CallArity, local_stack_size: LocalStackSize) {
+        let base = self.frame_base();
+        self.stack.shift_call(base, call_arity);
+        if let Some(ret) = self.rets.last_mut() {
+            ret.3 = call_arity;
+        }
+        self.sp_set(local_stack_size);
+    }
+
     /// Set the stack pointer to the top and push dummy argument for the local stack
     #[inline]
     pub fn sp_set(&mut self, local_stack_size: LocalStackSize) {
@@ -253,7 +504,7 @@ impl<'m, 'e, A, L, T, V: Valuable> ExecutionMachine<'m, 'e, A, L, T, V> {
     }
 }
 
-impl<'m, 'e, A, L, T, V: Valuable + core::fmt::Debug> ExecutionMachine<'m, 'e, A, L, T, V> {
+impl<'m, 'e, A, L, T, V: Valuable + core::fmt::Debug, H> ExecutionMachine<'m, 'e, A, L, T, V, H> {
     /// print the debug state of the execution machine in a writer
     pub fn debug_state<W: core::fmt::Write>(&self, writer: &mut W) -> Result<(), core::fmt::Error> {
         writeln!(writer, "ip={} sp={:?}", self.ip, self.sp.0)?;
@@ -310,6 +561,13 @@ pub enum ExecutionError {
         /// the actual structure length
         struct_len: usize,
     },
+    /// Trying to access a structure field by name that doesn't exist in this structure
+    StructFieldNotFound {
+        /// Constructor Id of the structure
+        constr: ConstrId,
+        /// the field name
+        field: ir::Ident,
+    },
     /// Value is not a function
     CallingNotFunc {
         /// the descriptor for the value that was not a fun
@@ -320,6 +578,11 @@ pub enum ExecutionError {
         /// The descriptor for the value that was not a struct
         value_is: ValueKind,
     },
+    /// Value is not an enumeration
+    ValueNotEnum {
+        /// The descriptor for the value that was not an enumeration
+        value_is: ValueKind,
+    },
     /// Value is not a valid conditional
     ValueNotConditional {
         /// The descriptor for the value that was not a conditional
@@ -332,15 +595,60 @@ pub enum ExecutionError {
         /// The descriptor for the value that was used
         value_got: ValueKind,
     },
+    /// No pattern of a match matched the value
+    MatchFailure {
+        /// The location of the match
+        span: Span,
+    },
     /// NIF return an error
     UserPanic {
         /// user message
         message: String,
     },
+    /// Not enough fuel remaining to execute the next instruction
+    ///
+    /// The execution can be resumed with `exec_continue` once more fuel has been added
+    OutOfFuel {
+        /// The fuel cost of the next instruction
+        needed: u64,
+    },
+    /// The call depth or the value stack size reached the limit of the execution params
+    StackOverflow {
+        /// The call depth when the limit was reached
+        depth: usize,
+        /// The call frames, from the function where the limit was reached to the initial call
+        trace: Vec<StackFrame>,
+    },
+    /// An error with the stack trace of the execution at the time of the error
+    Traced {
+        /// The error itself
+        error: Box<ExecutionError>,
+        /// The call frames, from the function where the error happened to the initial call
+        trace: Vec<StackFrame>,
+    },
+    /// The allocator quota of the execution is exceeded
+    OutOfMemory {
+        /// The number of bytes already allocated
+        allocated: usize,
+        /// The number of bytes of the allocation that exceeded the quota
+        requested: usize,
+    },
     /// Execution finished
     ExecutionFinished,
     /// NIF return a NotReady signal
+    ///
+    /// The execution is suspended on the NIF call, with the call still on the stack and
+    /// the instruction pointer on the call instruction, until resumed with `ExecutionMachine::resume_with`
     NotReady,
+    /// Trying to resume an execution that is not suspended on a NIF call
+    NotSuspended,
+    /// A value was thrown without any try handler to catch it
+    ///
+    /// The value is left on the top of the value stack
+    UncaughtThrow {
+        /// The descriptor of the value thrown
+        value_is: ValueKind,
+    },
     /// Abort
     Abort,
 }
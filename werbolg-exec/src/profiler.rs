@@ -0,0 +1,308 @@
+//! Profiler of the functions and NIFs of an execution
+//!
+//! The profiler is used as the [`ExecutionHooks`] of the execution machine, and counts every
+//! instruction executed for the stack of functions it was executed in. The stack follows
+//! the call frames of the machine (`rets`) from the calls and returns, a call in tail position
+//! replacing the function of the current frame. A NIF call costs the call instruction,
+//! attributed to the NIF.
+//!
+//! The stacks are kept as a tree of nodes, a node being a function called from the stack
+//! of its parent node, so that counting an instruction only updates the current node.
+//!
+//! The counts are exported as "folded stacks", one line per stack with its functions
+//! separated by `;` followed by the number of instructions, which is the input format
+//! of flamegraph tools.
+
+use crate::{ExecutionHooks, ExecutionMachine};
+use alloc::{collections::BTreeMap, vec, vec::Vec};
+use werbolg_compile::{Instruction, InstructionAddress};
+use werbolg_core::{FunId, NifId, ValueFun};
+
+/// A frame of a profiled stack
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ProfileFrame {
+    /// A function of the compilation unit
+    Fun(FunId),
+    /// A NIF of the environment
+    Nif(NifId),
+}
+
+/// Counters of a function or NIF
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ProfileCounters {
+    /// Number of calls
+    pub calls: u64,
+    /// Number of instructions executed in the function itself
+    pub exclusive: u64,
+    /// Number of instructions executed in the function and all the functions it called
+    pub inclusive: u64,
+}
+
+/// A node of the stacks tree, the function at the top of a stack
+#[derive(Debug)]
+struct ProfileNode {
+    /// The node of the stack without this function, None for the initial call
+    parent: Option<usize>,
+    frame: ProfileFrame,
+    /// The number of functions in the stack
+    depth: usize,
+    /// Number of instructions executed with this stack
+    count: u64,
+    /// Number of calls made to this stack
+    calls: u64,
+}
+
+/// Profiler counting the instructions executed and the calls per function and NIF
+#[derive(Debug, Default)]
+pub struct Profiler {
+    /// The node of the call frames of the machine, None before the initial call
+    current: Option<usize>,
+    /// The call instruction being executed, true for a call in tail position
+    call: Option<bool>,
+    nodes: Vec<ProfileNode>,
+    /// The node of each function called from a node, only used on calls
+    children: BTreeMap<(Option<usize>, ProfileFrame), usize>,
+}
+
+impl Profiler {
+    /// Create a new profiler, without any counts
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn enter(&mut self, frame: ProfileFrame) {
+        let parent = self.current;
+        let node = match self.children.get(&(parent, frame)) {
+            Some(node) => *node,
+            None => {
+                let node = self.nodes.len();
+                let depth = parent.map_or(0, |parent| self.nodes[parent].depth) + 1;
+                self.nodes.push(ProfileNode {
+                    parent,
+                    frame,
+                    depth,
+                    count: 0,
+                    calls: 0,
+                });
+                self.children.insert((parent, frame), node);
+                node
+            }
+        };
+        self.nodes[node].calls += 1;
+        self.current = Some(node);
+    }
+
+    fn leave(&mut self) {
+        self.current = self.current.and_then(|node| self.nodes[node].parent);
+    }
+
+    /// Count one instruction executed in the current stack
+    fn count(&mut self) {
+        if let Some(node) = self.current {
+            self.nodes[node].count += 1;
+        }
+    }
+
+    /// The functions of the stack of a node, from the initial call
+    fn stack(&self, node: Option<usize>) -> Vec<ProfileFrame> {
+        let mut stack = Vec::new();
+        let mut node = node;
+        while let Some(index) = node {
+            stack.push(self.nodes[index].frame);
+            node = self.nodes[index].parent;
+        }
+        stack.reverse();
+        stack
+    }
+
+    /// The number of instructions executed per stack
+    fn folded(&self) -> BTreeMap<Vec<ProfileFrame>, u64> {
+        self.nodes
+            .iter()
+            .enumerate()
+            .filter(|(_, node)| node.count > 0)
+            .map(|(index, node)| (self.stack(Some(index)), node.count))
+            .collect()
+    }
+
+    /// Get the counters of all the functions and NIFs that were called
+    pub fn counters(&self) -> BTreeMap<ProfileFrame, ProfileCounters> {
+        let mut counters = BTreeMap::<ProfileFrame, ProfileCounters>::new();
+        for node in self.nodes.iter() {
+            counters.entry(node.frame).or_default().calls += node.calls;
+        }
+
+        let mut seen = vec![];
+        for (stack, count) in self.folded().iter() {
+            if let Some(last) = stack.last() {
+                counters.entry(*last).or_default().exclusive += count;
+            }
+            // a recursive function only counts once for a stack
+            seen.clear();
+            for frame in stack.iter() {
+                if !seen.contains(frame) {
+                    seen.push(*frame);
+                    counters.entry(*frame).or_default().inclusive += count;
+                }
+            }
+        }
+        counters
+    }
+
+    /// Write the folded stacks, naming the functions and NIFs from the execution machine
+    pub fn write_folded<W: core::fmt::Write, A, L, T, V, H>(
+        &self,
+        writer: &mut W,
+        em: &ExecutionMachine<'_, '_, A, L, T, V, H>,
+    ) -> Result<(), core::fmt::Error> {
+        for (stack, count) in self.folded().iter() {
+            for (i, frame) in stack.iter().enumerate() {
+                if i > 0 {
+                    writer.write_char(';')?;
+                }
+                match frame {
+                    ProfileFrame::Fun(fun) => match &em.module.funs[*fun].name {
+                        Some(name) => writer.write_str(&name.0)?,
+                        None => write!(writer, "{:?}", fun)?,
+                    },
+                    ProfileFrame::Nif(nif) => writer.write_str(em.environ.nifs[*nif].name)?,
+                }
+            }
+            writeln!(writer, " {}", count)?;
+        }
+        Ok(())
+    }
+}
+
+impl<V> ExecutionHooks<V> for Profiler {
+    fn on_instruction(&mut self, _ip: InstructionAddress, instruction: &Instruction) {
+        self.call = match instruction {
+            Instruction::Call(_) => Some(false),
+            Instruction::TailCall(_) => Some(true),
+            _ => {
+                self.count();
+                None
+            }
+        };
+    }
+
+    fn on_call(&mut self, fun: ValueFun, _args: &[V]) {
+        let frame = match fun {
+            ValueFun::Native(nif) => ProfileFrame::Nif(nif),
+            ValueFun::Fun(fun) => ProfileFrame::Fun(fun),
+        };
+        match self.call.take() {
+            // the initial call of the execution, not made by a call instruction
+            None => {
+                self.current = None;
+                self.enter(frame);
+            }
+            // the call instruction of a NIF is attributed to the NIF
+            Some(_) if matches!(frame, ProfileFrame::Nif(_)) => {
+                self.enter(frame);
+                self.count();
+            }
+            Some(tail) => {
+                self.count();
+                if tail {
+                    self.leave();
+                }
+                self.enter(frame);
+            }
+        }
+    }
+
+    fn on_return(&mut self, _value: &V) {
+        self.leave();
+    }
+
+    fn on_catch(&mut self, depth: usize) {
+        while let Some(node) = self.current {
+            if self.nodes[node].depth <= depth + 1 {
+                break;
+            }
+            self.current = self.nodes[node].parent;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use werbolg_compile::CallArity;
+    use werbolg_core::id::IdF;
+
+    fn fun(index: usize) -> ProfileFrame {
+        ProfileFrame::Fun(FunId::from_collection_len(index))
+    }
+
+    /// Execute an instruction, and the call it makes
+    fn step(profiler: &mut Profiler, instruction: Instruction, call: Option<ValueFun>) {
+        ExecutionHooks::<()>::on_instruction(profiler, InstructionAddress::default(), &instruction);
+        if let Some(call) = call {
+            profiler.on_call(call, &[()]);
+        }
+    }
+
+    fn counters(calls: u64, exclusive: u64, inclusive: u64) -> ProfileCounters {
+        ProfileCounters {
+            calls,
+            exclusive,
+            inclusive,
+        }
+    }
+
+    #[test]
+    fn calls() {
+        let f = |index| ValueFun::Fun(FunId::from_collection_len(index));
+        let nif = ValueFun::Native(NifId::from_collection_len(0));
+        let mut profiler = Profiler::new();
+
+        // main runs 2 instructions and calls 1, which runs 1 instruction and tail calls 2,
+        // which calls the NIF and returns for 1 to main, which returns after 1 instruction
+        profiler.on_call(f(0), &[()]);
+        step(&mut profiler, Instruction::IgnoreOne, None);
+        step(&mut profiler, Instruction::IgnoreOne, None);
+        step(&mut profiler, Instruction::Call(CallArity(0)), Some(f(1)));
+        step(&mut profiler, Instruction::IgnoreOne, None);
+        step(
+            &mut profiler,
+            Instruction::TailCall(CallArity(0)),
+            Some(f(2)),
+        );
+        step(&mut profiler, Instruction::Call(CallArity(0)), Some(nif));
+        profiler.on_return(&());
+        step(&mut profiler, Instruction::Ret, None);
+        profiler.on_return(&());
+        step(&mut profiler, Instruction::Ret, None);
+        profiler.on_return(&());
+
+        let nif = ProfileFrame::Nif(NifId::from_collection_len(0));
+        let expected = [
+            (fun(0), counters(1, 4, 8)),
+            (fun(1), counters(1, 2, 2)),
+            (fun(2), counters(1, 1, 2)),
+            (nif, counters(1, 1, 1)),
+        ];
+        assert_eq!(profiler.counters(), expected.into_iter().collect());
+        assert_eq!(profiler.folded()[&vec![fun(0), fun(2), nif]], 1);
+    }
+
+    #[test]
+    fn catch() {
+        let f = |index| ValueFun::Fun(FunId::from_collection_len(index));
+        let mut profiler = Profiler::new();
+
+        // 2 calls deep, and caught in the frame of the first call
+        profiler.on_call(f(0), &[()]);
+        step(&mut profiler, Instruction::Call(CallArity(0)), Some(f(1)));
+        step(&mut profiler, Instruction::Call(CallArity(0)), Some(f(2)));
+        step(&mut profiler, Instruction::Throw, None);
+        ExecutionHooks::<()>::on_catch(&mut profiler, 1);
+        step(&mut profiler, Instruction::IgnoreOne, None);
+
+        assert_eq!(profiler.stack(profiler.current), vec![fun(0), fun(1)]);
+        assert_eq!(profiler.folded()[&vec![fun(0), fun(1)]], 2);
+        assert_eq!(profiler.counters()[&fun(1)], counters(1, 2, 3));
+    }
+}
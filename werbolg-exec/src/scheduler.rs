@@ -0,0 +1,442 @@
+//! Cooperative scheduler of tasks sharing a compilation unit and an execution environment
+//!
+//! Each task is an execution machine, and the scheduler runs the runnable tasks in turn,
+//! executing a slice of instructions of each with [`step`] before moving to the next one.
+//!
+//! The NIFs of the scheduler (see [`SchedulerNifs`]) suspend the execution like any NIF returning
+//! [`ExecutionError::NotReady`], and the scheduler recognizes the suspended call to act on it:
+//!
+//! * `spawn f args..` starts a new task calling the function or the closure `f` with the arguments,
+//!   returning the task. The closure is created again in the new task, with the same captured values
+//! * `send task message` puts the message in the mailbox of the task, returning the message
+//! * `receive` takes the oldest message of the mailbox, waiting for one if the mailbox is empty
+//! * `self` returns the task itself
+//!
+//! Any other NIF returning NotReady parks the task, until the host wakes it with [`Scheduler::wake`].
+//! A metered task running out of fuel is parked too, until the host refuels it with [`Scheduler::add_fuel`].
+//!
+//! The values given to another task (messages, spawn arguments and captured values) are moved to another
+//! execution machine, so they can't refer to the composite values of an [`crate::ArenaAllocator`].
+
+use crate::allocator::{composite_size, WAllocator};
+use crate::exec::{initialize, initialize_call, step, NIFCall, NIF};
+use crate::{ExecutionEnviron, ExecutionError, ExecutionHooks, ExecutionMachine, Valuable};
+use alloc::{collections::VecDeque, string::String, vec::Vec};
+use core::num::NonZeroUsize;
+use werbolg_compile::{CallArity, CompilationUnit, Environment};
+use werbolg_core::id::IdF;
+use werbolg_core::idvec::IdVec;
+use werbolg_core::{FunId, Ident, Namespace, NifId, ValueFun};
+
+/// Task Id
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct TaskId(u32);
+
+impl IdF for TaskId {
+    fn as_index(self) -> usize {
+        self.0 as usize
+    }
+
+    fn from_slice_len<T>(slice: &[T]) -> Self {
+        Self(slice.len() as u32)
+    }
+
+    fn from_collection_len(len: usize) -> Self {
+        Self(len as u32)
+    }
+
+    fn remap(left: Self, right: Self) -> Self {
+        Self(left.0 + right.0)
+    }
+}
+
+impl core::fmt::Debug for TaskId {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "T{:?}", self.0)
+    }
+}
+
+/// The NIFs of the scheduler, as registered in the environment
+#[derive(Clone, Copy, Debug)]
+pub struct SchedulerNifs {
+    /// Start a new task
+    pub spawn: NifId,
+    /// Send a message to a task
+    pub send: NifId,
+    /// Receive a message from the mailbox of the current task
+    pub receive: NifId,
+    /// Get the current task
+    pub current: NifId,
+}
+
+impl SchedulerNifs {
+    /// Add the `spawn`, `send`, `receive` and `self` NIFs to the environment in a namespace
+    pub fn register<'m, 'e, A, L, T, V, H>(
+        env: &mut Environment<NIF<'m, 'e, A, L, T, V, H>, V>,
+        namespace: &Namespace,
+    ) -> Self {
+        let mut add = |name: &'static str| {
+            let nif = NIF {
+                name,
+                call: NIFCall::Pure(scheduler_call),
+            };
+            env.add_nif(namespace, Ident::from(name), nif)
+        };
+        Self {
+            spawn: add("spawn"),
+            send: add("send"),
+            receive: add("receive"),
+            current: add("self"),
+        }
+    }
+}
+
+/// The NIFs of the scheduler only suspend the execution, the scheduler doing the actual work
+fn scheduler_call<V>(_args: &[V]) -> Result<V, ExecutionError> {
+    Err(ExecutionError::NotReady)
+}
+
+/// Function creating an execution machine for the compilation unit and the environment
+pub type NewMachine<'m, 'e, A, L, T, V, H = ()> = fn(
+    &'m CompilationUnit<L>,
+    &'e ExecutionEnviron<'m, 'e, A, L, T, V, H>,
+) -> ExecutionMachine<'m, 'e, A, L, T, V, H>;
+
+/// User driven Scheduler params
+pub struct SchedulerParams<'m, 'e, A, L, T, V, H = ()> {
+    /// number of instructions executed by a task before switching to the next one
+    pub slice: NonZeroUsize,
+    /// the NIFs of the scheduler
+    pub nifs: SchedulerNifs,
+    /// function to create the execution machine of a new task
+    pub new_machine: NewMachine<'m, 'e, A, L, T, V, H>,
+    /// function to map a task to a value
+    pub task_to_value: fn(TaskId) -> V,
+    /// function to get the task of a value, or None if the value is not a task
+    pub value_to_task: fn(&V) -> Option<TaskId>,
+}
+
+/// State of a task
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TaskState {
+    /// The task is waiting for its turn to run
+    Runnable,
+    /// The task is waiting for a message in its mailbox
+    Receiving,
+    /// The task is suspended on a NIF call or out of fuel, until woken or refuelled by the host
+    Parked,
+    /// The task finished with a value
+    Finished,
+    /// The task stopped on an error
+    Failed,
+}
+
+/// An event of a task reported by the scheduler
+#[derive(Debug)]
+pub enum TaskEvent<V> {
+    /// The task finished with the value
+    Finished(TaskId, V),
+    /// The task stopped on the error
+    Failed(TaskId, ExecutionError),
+    /// The task is suspended on a NIF call, and need to be woken with the value of the call
+    Parked(TaskId),
+    /// The task ran out of fuel, and need to be refuelled to carry on
+    OutOfFuel(TaskId),
+}
+
+struct Task<'m, 'e, A, L, T, V, H> {
+    em: ExecutionMachine<'m, 'e, A, L, T, V, H>,
+    state: TaskState,
+    mailbox: VecDeque<V>,
+}
+
+/// The call a task is suspended on
+enum Suspended<V> {
+    Spawn(V, Vec<V>),
+    Send(V, V),
+    Receive,
+    Current,
+    Other,
+}
+
+/// Cooperative scheduler running tasks in turn
+pub struct Scheduler<'m, 'e, A, L, T, V, H = ()> {
+    module: &'m CompilationUnit<L>,
+    environ: &'e ExecutionEnviron<'m, 'e, A, L, T, V, H>,
+    params: SchedulerParams<'m, 'e, A, L, T, V, H>,
+    tasks: IdVec<TaskId, Task<'m, 'e, A, L, T, V, H>>,
+    run_queue: VecDeque<TaskId>,
+    /// The events not reported yet, e.g. of the tasks finishing as soon as they are spawned
+    events: VecDeque<TaskEvent<V>>,
+}
+
+impl<'m, 'e, A: WAllocator<Value = V>, L, T, V: Valuable, H: ExecutionHooks<V>>
+    Scheduler<'m, 'e, A, L, T, V, H>
+{
+    /// Create a new scheduler without any task
+    pub fn new(
+        module: &'m CompilationUnit<L>,
+        environ: &'e ExecutionEnviron<'m, 'e, A, L, T, V, H>,
+        params: SchedulerParams<'m, 'e, A, L, T, V, H>,
+    ) -> Self {
+        Self {
+            module,
+            environ,
+            params,
+            tasks: IdVec::new(),
+            run_queue: VecDeque::new(),
+            events: VecDeque::new(),
+        }
+    }
+
+    /// Start a new task calling the function with the arguments
+    pub fn spawn(&mut self, fun: FunId, args: &[V]) -> Result<TaskId, ExecutionError> {
+        let mut em = (self.params.new_machine)(self.module, self.environ);
+        let value = initialize(&mut em, fun, args)?;
+        Ok(self.add_task(em, value))
+    }
+
+    /// Start a new task calling a function value of a task with the arguments
+    ///
+    /// A closure is created again with the allocator of the new task
+    fn spawn_value(
+        &mut self,
+        parent: TaskId,
+        fun: &V,
+        args: &[V],
+    ) -> Result<TaskId, ExecutionError> {
+        let mut em = (self.params.new_machine)(self.module, self.environ);
+        em.allocator.reset();
+        let call = match self.tasks[parent].em.allocator.closure(fun) {
+            Some((fun, captured)) => {
+                em.allocator.allocate(composite_size::<V>(captured.len()))?;
+                em.allocator.make_closure(fun, captured)
+            }
+            None => fun.clone(),
+        };
+        let value = initialize_call(&mut em, call, args)?;
+        Ok(self.add_task(em, value))
+    }
+
+    /// Add a task with its initialized execution machine, and the value if it finished already
+    fn add_task(
+        &mut self,
+        em: ExecutionMachine<'m, 'e, A, L, T, V, H>,
+        value: Option<V>,
+    ) -> TaskId {
+        let state = match value {
+            None => TaskState::Runnable,
+            Some(_) => TaskState::Finished,
+        };
+        let id = self.tasks.push(Task {
+            em,
+            state,
+            mailbox: VecDeque::new(),
+        });
+        match value {
+            None => self.run_queue.push_back(id),
+            Some(value) => self.events.push_back(TaskEvent::Finished(id, value)),
+        }
+        id
+    }
+
+    /// Get the state of a task, or None if the task doesn't exist
+    pub fn state(&self, task: TaskId) -> Option<TaskState> {
+        self.tasks.get(task).map(|t| t.state)
+    }
+
+    /// Get the execution machine of a task, or None if the task doesn't exist
+    pub fn machine(&self, task: TaskId) -> Option<&ExecutionMachine<'m, 'e, A, L, T, V, H>> {
+        self.tasks.get(task).map(|t| &t.em)
+    }
+
+    /// Send a message to a task from the host, returning if the task can still receive messages
+    pub fn send(&mut self, task: TaskId, message: V) -> Result<bool, ExecutionError> {
+        self.deliver(task, message)
+    }
+
+    /// Wake a parked task, using the value as the result of the NIF call it is suspended on
+    ///
+    /// A NotSuspended error is returned if the task is not parked
+    pub fn wake(&mut self, task: TaskId, value: V) -> Result<(), ExecutionError> {
+        match self.tasks.get(task) {
+            Some(t) if t.state == TaskState::Parked => {}
+            _ => return Err(ExecutionError::NotSuspended),
+        }
+        self.tasks[task].em.resume_with(value)?;
+        self.tasks[task].state = TaskState::Runnable;
+        self.run_queue.push_back(task);
+        Ok(())
+    }
+
+    /// Add fuel to a task parked out of fuel, making it runnable again
+    ///
+    /// A NotSuspended error is returned if the task is not parked out of fuel
+    pub fn add_fuel(&mut self, task: TaskId, fuel: u64) -> Result<(), ExecutionError> {
+        match self.tasks.get(task) {
+            Some(t) if t.state == TaskState::Parked && t.em.suspended.is_none() => {}
+            _ => return Err(ExecutionError::NotSuspended),
+        }
+        self.tasks[task].em.add_fuel(fuel);
+        self.tasks[task].state = TaskState::Runnable;
+        self.run_queue.push_back(task);
+        Ok(())
+    }
+
+    /// Run the tasks in turn, until one of them finishes, fails or is parked
+    ///
+    /// None is returned when there is no runnable task left, all the tasks being finished,
+    /// parked or waiting for a message
+    pub fn run(&mut self) -> Option<TaskEvent<V>> {
+        if let Some(event) = self.events.pop_front() {
+            return Some(event);
+        }
+        while let Some(id) = self.run_queue.pop_front() {
+            if let Some(event) = self.run_slice(id) {
+                return Some(event);
+            }
+            if self.tasks[id].state == TaskState::Runnable {
+                self.run_queue.push_back(id);
+            }
+        }
+        None
+    }
+
+    /// Run a slice of instructions of a task
+    fn run_slice(&mut self, id: TaskId) -> Option<TaskEvent<V>> {
+        for _ in 0..self.params.slice.get() {
+            let result = match step(&mut self.tasks[id].em) {
+                Ok(None) => continue,
+                Ok(Some(value)) => Ok(value),
+                Err(ExecutionError::NotReady) => match self.process_suspended(id) {
+                    Ok(()) if self.tasks[id].state == TaskState::Runnable => continue,
+                    Ok(()) if self.tasks[id].state == TaskState::Parked => {
+                        return Some(TaskEvent::Parked(id))
+                    }
+                    Ok(()) => return None,
+                    Err(e) => Err(e),
+                },
+                Err(ExecutionError::OutOfFuel { .. }) => {
+                    self.tasks[id].state = TaskState::Parked;
+                    return Some(TaskEvent::OutOfFuel(id));
+                }
+                Err(e) => Err(e),
+            };
+            return Some(match result {
+                Ok(value) => {
+                    self.finish(id, TaskState::Finished);
+                    TaskEvent::Finished(id, value)
+                }
+                Err(error) => {
+                    self.finish(id, TaskState::Failed);
+                    TaskEvent::Failed(id, error)
+                }
+            });
+        }
+        None
+    }
+
+    /// Act on the NIF call the task is suspended on
+    fn process_suspended(&mut self, id: TaskId) -> Result<(), ExecutionError> {
+        let suspended = self.suspended(id)?;
+        let value = match suspended {
+            Suspended::Spawn(fun, args) => {
+                let task = self.spawn_value(id, &fun, &args)?;
+                (self.params.task_to_value)(task)
+            }
+            Suspended::Send(task, message) => {
+                let Some(task) = (self.params.value_to_task)(&task) else {
+                    return Err(ExecutionError::UserPanic {
+                        message: String::from("send: the destination is not a task"),
+                    });
+                };
+                self.deliver(task, message.clone())?;
+                message
+            }
+            Suspended::Receive => match self.tasks[id].mailbox.pop_front() {
+                Some(message) => message,
+                None => {
+                    self.tasks[id].state = TaskState::Receiving;
+                    return Ok(());
+                }
+            },
+            Suspended::Current => (self.params.task_to_value)(id),
+            Suspended::Other => {
+                self.tasks[id].state = TaskState::Parked;
+                return Ok(());
+            }
+        };
+        self.tasks[id].em.resume_with(value)
+    }
+
+    /// Get the NIF call the task is suspended on
+    fn suspended(&self, id: TaskId) -> Result<Suspended<V>, ExecutionError> {
+        let em = &self.tasks[id].em;
+        let Some(arity) = em.suspended else {
+            return Err(ExecutionError::NotSuspended);
+        };
+        let (call, args) = em.stack.get_call_and_args(arity);
+        let Some(ValueFun::Native(nif)) = call.fun() else {
+            return Ok(Suspended::Other);
+        };
+        let nifs = &self.params.nifs;
+        let check_arity = |expected: u8| {
+            if args.len() == expected as usize {
+                Ok(())
+            } else {
+                Err(ExecutionError::ArityError {
+                    expected: CallArity(expected),
+                    got: arity,
+                })
+            }
+        };
+        Ok(if nif == nifs.spawn {
+            let Some((fun, args)) = args.split_first() else {
+                return Err(ExecutionError::ArityError {
+                    expected: CallArity(1),
+                    got: arity,
+                });
+            };
+            Suspended::Spawn(fun.clone(), args.to_vec())
+        } else if nif == nifs.send {
+            check_arity(2)?;
+            Suspended::Send(args[0].clone(), args[1].clone())
+        } else if nif == nifs.receive {
+            check_arity(0)?;
+            Suspended::Receive
+        } else if nif == nifs.current {
+            check_arity(0)?;
+            Suspended::Current
+        } else {
+            Suspended::Other
+        })
+    }
+
+    /// Put a message in the mailbox of a task, handing it directly to the task if it is waiting for one
+    fn deliver(&mut self, task: TaskId, message: V) -> Result<bool, ExecutionError> {
+        let Some(t) = self.tasks.get(task) else {
+            return Ok(false);
+        };
+        match t.state {
+            TaskState::Finished | TaskState::Failed => Ok(false),
+            TaskState::Receiving => {
+                let t = &mut self.tasks[task];
+                t.em.resume_with(message)?;
+                t.state = TaskState::Runnable;
+                self.run_queue.push_back(task);
+                Ok(true)
+            }
+            TaskState::Runnable | TaskState::Parked => {
+                self.tasks[task].mailbox.push_back(message);
+                Ok(true)
+            }
+        }
+    }
+
+    /// Terminate a task, dropping the messages of its mailbox
+    fn finish(&mut self, id: TaskId, state: TaskState) {
+        let task = &mut self.tasks[id];
+        task.state = state;
+        task.mailbox.clear();
+    }
+}
@@ -0,0 +1,208 @@
+//! Snapshot of a paused execution
+//!
+//! The snapshot contains the state of the execution machine (the instruction and stack pointers,
+//! the call frames, the value stack, the fuel, the suspended call, the try handlers and the state
+//! of the allocator), and the fingerprint of the compilation unit the execution belongs to
+//! (see [`werbolg_compile::CompilationUnit::fingerprint`]), so that it is only restored against the same unit.
+//! The values are encoded with a user provided [`ValueCodec`], and the allocator state with
+//! [`WAllocator::save_state`].
+
+use crate::{ExecutionMachine, Handler, StackPointer, Valuable, WAllocator};
+use alloc::vec::Vec;
+use werbolg_compile::{CallArity, InstructionAddress, LocalStackSize};
+use werbolg_core::id::IdF;
+use werbolg_core::serialize::{DecodeError, Reader, Writer};
+
+const MAGIC: [u8; 4] = *b"WBSN";
+
+/// The version of the snapshot binary format written by the encoder
+pub const SNAPSHOT_FORMAT_VERSION: u16 = 1;
+
+/// User driven serialization of the values
+#[derive(Clone)]
+pub struct ValueCodec<V> {
+    /// Append the binary representation of a value to the buffer
+    pub encode: fn(&V, &mut Vec<u8>),
+    /// Decode a value from its binary representation, or None if it is not valid
+    pub decode: fn(&[u8]) -> Option<V>,
+}
+
+/// Error when restoring a snapshot
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SnapshotError {
+    /// The snapshot is not valid binary data
+    Decode(DecodeError),
+    /// The snapshot was taken for a different compilation unit
+    FingerprintMismatch {
+        /// The fingerprint of the unit of the execution machine
+        expected: u64,
+        /// The fingerprint of the unit of the snapshot
+        got: u64,
+    },
+    /// The value at this index in the stack cannot be decoded by the value codec
+    InvalidValue(usize),
+    /// The state is not consistent with the compilation unit
+    InvalidState,
+    /// The allocator of the execution machine has a state that can't be captured in a snapshot
+    AllocatorNotCaptured,
+    /// The allocator state of the snapshot can't be restored by the allocator of the execution machine
+    InvalidAllocatorState,
+}
+
+impl From<DecodeError> for SnapshotError {
+    fn from(e: DecodeError) -> Self {
+        SnapshotError::Decode(e)
+    }
+}
+
+impl<'m, 'e, A: WAllocator<Value = V>, L, T, V: Valuable, H>
+    ExecutionMachine<'m, 'e, A, L, T, V, H>
+{
+    /// Take a snapshot of the execution, with the fingerprint of the compilation unit of this machine
+    ///
+    /// An AllocatorNotCaptured error is returned if the state of the allocator can't be saved
+    pub fn snapshot(&self, codec: &ValueCodec<V>) -> Result<Vec<u8>, SnapshotError> {
+        let mut allocator_state = Vec::new();
+        self.allocator.save_state(&mut allocator_state)?;
+
+        let mut w = Writer::new(&MAGIC, SNAPSHOT_FORMAT_VERSION);
+        w.u64(self.module.fingerprint());
+        w.id(self.ip);
+        w.u64(self.sp.0 as u64);
+        w.u16(self.current_stack_size.0);
+        write_option(&mut w, self.fuel, |w, fuel| w.u64(fuel));
+        write_option(&mut w, self.suspended, |w, arity| w.u8(arity.0));
+
+        w.list(self.rets.iter(), |w, (ip, sp, stack_size, arity)| {
+            w.id(*ip);
+            w.u64(sp.0 as u64);
+            w.u16(stack_size.0);
+            w.u8(arity.0);
+        });
+        w.list(self.handlers.iter(), |w, handler| {
+            w.id(handler.ip);
+            w.u64(handler.depth as u64);
+            w.u64(handler.sp.0 as u64);
+            w.u16(handler.stack_size.0);
+            w.u64(handler.stack_len as u64);
+        });
+
+        let mut value_buf = Vec::new();
+        w.length(self.stack.values.len());
+        for value in self.stack.values.iter() {
+            value_buf.clear();
+            (codec.encode)(value, &mut value_buf);
+            w.bytes(&value_buf);
+        }
+        w.bytes(&allocator_state);
+        Ok(w.finalize())
+    }
+
+    /// Restore the execution from a snapshot, replacing the current state of the machine
+    ///
+    /// The snapshot needs to be taken for the same compilation unit as the one of this machine,
+    /// otherwise it is rejected. Once restored, the execution can carry on with `exec_continue`
+    pub fn restore(&mut self, data: &[u8], codec: &ValueCodec<V>) -> Result<(), SnapshotError> {
+        let mut r = Reader::new(data, &MAGIC, SNAPSHOT_FORMAT_VERSION)?;
+        let fingerprint = self.module.fingerprint();
+        let got = r.u64()?;
+        if got != fingerprint {
+            return Err(SnapshotError::FingerprintMismatch {
+                expected: fingerprint,
+                got,
+            });
+        }
+        let ip: InstructionAddress = r.id()?;
+        let sp = StackPointer(r.u64()? as usize);
+        let current_stack_size = LocalStackSize(r.u16()?);
+        let fuel = read_option(&mut r, |r| r.u64())?;
+        let suspended = read_option(&mut r, |r| r.u8().map(CallArity))?;
+        let rets = r.list(|r| {
+            Ok((
+                r.id()?,
+                StackPointer(r.u64()? as usize),
+                LocalStackSize(r.u16()?),
+                CallArity(r.u8()?),
+            ))
+        })?;
+        let handlers = r.list(|r| {
+            Ok(Handler {
+                ip: r.id()?,
+                depth: r.u64()? as usize,
+                sp: StackPointer(r.u64()? as usize),
+                stack_size: LocalStackSize(r.u16()?),
+                stack_len: r.u64()? as usize,
+            })
+        })?;
+
+        let nb_values = r.length()?;
+        let mut values = Vec::new();
+        for index in 0..nb_values {
+            let value = (codec.decode)(r.bytes()?).ok_or(SnapshotError::InvalidValue(index))?;
+            values.push(value);
+        }
+        let allocator_state = r.bytes()?;
+        r.finalize()?;
+
+        // the state is checked to not make the execution panic
+        let code_len = self.module.code.iter().count();
+        let valid_ip = |ip: InstructionAddress| ip.as_index() < code_len;
+        let frame_end = sp.0 + current_stack_size.0 as usize;
+        let frame_arity = match rets.last() {
+            None => 0,
+            Some((_, _, _, arity)) => arity.0 as usize + 1,
+        };
+        let suspended_arity = suspended.map_or(0, |arity: CallArity| arity.0 as usize + 1);
+        if !valid_ip(ip)
+            || frame_end > values.len()
+            || frame_arity > sp.0
+            || suspended_arity > values.len()
+            || rets
+                .iter()
+                .any(|(ip, sp, _, _)| !valid_ip(*ip) || sp.0 > values.len())
+            || handlers.iter().any(|handler| {
+                !valid_ip(handler.ip)
+                    || handler.depth > rets.len()
+                    || handler.stack_len > values.len()
+                    || handler.sp.0 + handler.stack_size.0 as usize > handler.stack_len
+            })
+        {
+            return Err(SnapshotError::InvalidState);
+        }
+
+        self.allocator.restore_state(allocator_state)?;
+        self.ip = ip;
+        self.sp = sp;
+        self.current_stack_size = current_stack_size;
+        self.fuel = fuel;
+        self.suspended = suspended;
+        self.rets = rets;
+        self.handlers = handlers;
+        self.stack.values = values;
+        Ok(())
+    }
+}
+
+fn write_option<T: Copy>(w: &mut Writer, v: Option<T>, f: impl FnOnce(&mut Writer, T)) {
+    match v {
+        None => w.u8(0),
+        Some(v) => {
+            w.u8(1);
+            f(w, v)
+        }
+    }
+}
+
+fn read_option<'a, T>(
+    r: &mut Reader<'a>,
+    f: impl FnOnce(&mut Reader<'a>) -> Result<T, DecodeError>,
+) -> Result<Option<T>, DecodeError> {
+    match r.u8()? {
+        0 => Ok(None),
+        1 => f(r).map(Some),
+        tag => Err(DecodeError::InvalidTag {
+            what: "option",
+            tag,
+        }),
+    }
+}
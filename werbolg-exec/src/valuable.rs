@@ -1,4 +1,6 @@
-use werbolg_core::{ConstrId, ValueFun};
+use alloc::vec::Vec;
+use werbolg_compile::VariantIndex;
+use werbolg_core::{ConstrId, FunId, ValueFun};
 
 /// A mostly for error and debug useful descriptor for a type of value
 pub type ValueKind = &'static [u8; 8];
@@ -14,15 +16,33 @@ pub trait Valuable: Clone {
     /// Get the a function value from a Valuable object, or None if not valid
     fn fun(&self) -> Option<ValueFun>;
 
+    /// Get a closure (the function and its captured values) from a Valuable object, or None if not valid
+    fn closure(&self) -> Option<(FunId, &[Self])>;
+
     /// Get a structure out of a Valuable object, or None if not valid
     fn structure(&self) -> Option<(ConstrId, &[Self])>;
 
-    /// Get the elements #index of a Valuable object, or None if not valid
+    /// Get an enumeration value (the enumeration, the variant and its fields) out of a Valuable object, or None if not valid
+    fn enumeration(&self) -> Option<(ConstrId, VariantIndex, &[Self])>;
+
+    /// Get the elements #index of a Valuable object (e.g. the element of a list), or None if not valid
     fn index(&self, index: usize) -> Option<&Self>;
 
     /// Create a Fun valuable object
     fn make_fun(fun: ValueFun) -> Self;
 
+    /// Create a Closure valuable object, from a function and the values it captures
+    fn make_closure(fun: FunId, captured: Vec<Self>) -> Self;
+
+    /// Create a List valuable object from its elements
+    fn make_list(elements: Vec<Self>) -> Self;
+
+    /// Create a Struct valuable object, from the structure and its fields in order
+    fn make_struct(constr: ConstrId, fields: Vec<Self>) -> Self;
+
+    /// Create an Enum valuable object, from the enumeration, the variant and the fields of the variant
+    fn make_enum(constr: ConstrId, variant: VariantIndex, fields: Vec<Self>) -> Self;
+
     /// Create a dummy parameter to push on the stack.
     fn make_dummy() -> Self;
 }
@@ -190,10 +190,11 @@ impl<'a> Parser<'a> {
 }
 
 fn parse_if(list_span: Span, exprs: Vec<Spanned<Ast>>) -> Result<Ast, ParseError> {
-    if exprs.len() != 3 {
+    // (if cond then else)
+    if exprs.len() != 4 {
         return Err(ParseError::IfArityFailed { if_span: list_span });
     }
-    let mut e = exprs.into_iter();
+    let mut e = exprs.into_iter().skip(1);
     let cond_expr = e.next().unwrap();
     let then_expr = e.next().unwrap();
     let else_expr = e.next().unwrap();
@@ -81,6 +81,10 @@ fn rewrite_expr(span_expr: &(parse::Expr, parse::Span)) -> ir::Expr {
             }
             ir::Expr::Call(span_expr.1.clone(), exprs)
         }
+        parse::Expr::Field(expr, (field, field_span)) => ir::Expr::FieldByName(
+            Box::new(rewrite_expr(expr)),
+            Spanned::new(field_span.clone(), ir::Ident::from(field.as_str())),
+        ),
         parse::Expr::If(cond, then_expr, else_expr) => ir::Expr::If {
             span: span_expr.1.clone(),
             cond: rewrite_expr_spanbox(cond),
@@ -64,7 +64,7 @@ fn lexer() -> impl Parser<char, Vec<(Token, Span)>, Error = Simple<char>> {
         .map(Token::Op);
 
     // A parser for control characters (delimiters, semicolons, etc.)
-    let ctrl = one_of("()[]{};,").map(|c| Token::Ctrl(c));
+    let ctrl = one_of("()[]{};,.").map(|c| Token::Ctrl(c));
 
     // A parser for identifiers and keywords
     let ident = text::ident().map(|ident: String| match ident.as_str() {
@@ -131,9 +131,16 @@ pub enum Expr {
     Then(Box<Spanned<Self>>, Box<Spanned<Self>>),
     Binary(Box<Spanned<Self>>, BinaryOp, Box<Spanned<Self>>),
     Call(Box<Spanned<Self>>, Vec<Spanned<Self>>),
+    Field(Box<Spanned<Self>>, Spanned<String>),
     If(Box<Spanned<Self>>, Box<Spanned<Self>>, Box<Spanned<Self>>),
 }
 
+// The operations following an expression: a call or a field access
+enum Postfix {
+    Call(Vec<Spanned<Expr>>),
+    Field(String),
+}
+
 // A function node in the AST.
 #[derive(Debug)]
 pub struct Func {
@@ -214,17 +221,26 @@ fn expr_parser() -> impl Parser<Token, Spanned<Expr>, Error = Simple<Token>> + C
                     |span| (Expr::Error, span),
                 ));
 
-            // Function calls have very high precedence so we prioritise them
+            // Function calls and field accesses have very high precedence so we prioritise them
             let call = atom
                 .then(
                     items
                         .delimited_by(just(Token::Ctrl('(')), just(Token::Ctrl(')')))
-                        .map_with_span(|args, span: Span| (args, span))
+                        .map(Postfix::Call)
+                        .or(just(Token::Ctrl('.'))
+                            .ignore_then(ident)
+                            .map(Postfix::Field))
+                        .map_with_span(|postfix, span: Span| (postfix, span))
                         .repeated(),
                 )
-                .foldl(|f, args| {
-                    let span = f.1.start..args.1.end;
-                    (Expr::Call(Box::new(f), args.0), span)
+                .foldl(|f, (postfix, postfix_span)| {
+                    let span = f.1.start..postfix_span.end;
+                    match postfix {
+                        Postfix::Call(args) => (Expr::Call(Box::new(f), args), span),
+                        Postfix::Field(field) => {
+                            (Expr::Field(Box::new(f), (field, postfix_span)), span)
+                        }
+                    }
                 });
 
             // Product ops (multiply and divide) have equal precedence
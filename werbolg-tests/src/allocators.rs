@@ -0,0 +1,141 @@
+//! Execution with the quota and arena allocators
+
+use crate::fixture::*;
+use alloc::{boxed::Box, vec, vec::Vec};
+use werbolg_compile::{compile, CompilationParams, Environment};
+use werbolg_core::{Expr, Ident, Namespace};
+use werbolg_exec::{
+    ArenaAllocator, ExecutionEnviron, ExecutionError, ExecutionMachine, NIFCall, QuotaAllocator,
+    SnapshotError, WAllocator, NIF,
+};
+
+#[test]
+fn allocator_quota() {
+    // a list of 10 lists of 3 numbers
+    let row = Expr::List(0..0, vec![number("1"), number("2"), number("3")]);
+    let body = Expr::List(0..0, vec![row; 10]);
+    let mut module = parse("");
+    module.statements.push(function("main", &[], body));
+
+    // the NIFs are typed with the allocator, so the quota allocator needs its own environment
+    let mut env = Environment::<NIF<QuotaAllocator<Value>, u64, (), Value>, Value>::new();
+    let unit = compile(
+        &CompilationParams { literal_mapper },
+        vec![(Namespace::root(), module)],
+        &mut env,
+    )
+    .expect("compiled");
+    let ee = ExecutionEnviron::from_compile_environment(env.finalize());
+    let main = fun_id(&unit, "main");
+    let machine = |max_bytes: usize| {
+        let allocator = QuotaAllocator::new(max_bytes);
+        ExecutionMachine::new(&unit, &ee, execution_params(), allocator, ())
+    };
+
+    let mut em = machine(usize::MAX);
+    let result = werbolg_exec::exec(&mut em, main, &[]);
+    assert!(matches!(result, Ok(Value::List(rows)) if rows.len() == 10));
+    assert_eq!(em.allocator.objects, 11);
+    let needed = em.allocator.bytes;
+
+    // the accounting is per execution
+    let mut em = machine(needed);
+    for _ in 0..2 {
+        assert!(werbolg_exec::exec(&mut em, main, &[]).is_ok());
+        assert_eq!(em.allocator.bytes, needed);
+    }
+
+    let mut em = machine(needed - 1);
+    let result = werbolg_exec::exec(&mut em, main, &[]);
+    assert!(matches!(
+        result,
+        Err(ExecutionError::OutOfMemory { allocated, .. }) if allocated < needed
+    ));
+    assert_eq!(em.allocator.objects, 10);
+    // the rows of the list that failed to be allocated are still on the stack
+    let stack = em.stack.iter_pos().map(|(_, v)| v).collect::<Vec<_>>();
+    let rows = &stack[stack.len() - 10..];
+    assert!(rows
+        .iter()
+        .all(|row| matches!(row, Value::List(row) if row.len() == 3)));
+}
+
+#[test]
+fn arena_allocator() {
+    let describe = Expr::Match {
+        span: 0..0,
+        expr: Box::new(local("s")),
+        arms: vec![
+            arm(
+                variant_pattern("shape", "circle", vec![bind("r")]),
+                local("r"),
+            ),
+            arm(
+                variant_pattern("shape", "rect", vec![bind("w"), bind("h")]),
+                call(vec![local("+"), local("w"), local("h")]),
+            ),
+        ],
+    };
+    let circle = |r: Expr| call(vec![variant("shape", "circle"), r]);
+    let rect = |w: Expr, h: &str| call(vec![variant("shape", "rect"), w, number(h)]);
+    let add_by = call(vec![local("add_by"), number("30")]);
+    let body = Expr::List(
+        0..0,
+        vec![
+            call(vec![local("describe"), circle(number("4"))]),
+            call(vec![local("describe"), rect(number("9"), "3")]),
+            call(vec![add_by, number("12")]),
+            rect(circle(number("5")), "2"),
+        ],
+    );
+    let mut module = shape_module(body);
+    module
+        .statements
+        .push(function("describe", &["s"], describe));
+    module
+        .statements
+        .extend(parse("(define (add_by n) (define (inner m) (+ m n)) inner)").statements);
+
+    // the NIFs are typed with the allocator, so the arena allocator needs its own environment
+    let mut env = Environment::<NIF<ArenaAllocator<Value>, u64, (), Value>, Value>::new();
+    let plus = NIF {
+        name: "+",
+        call: NIFCall::Pure(nif_plus),
+    };
+    env.add_nif(&Namespace::root(), Ident::from("+"), plus);
+    let unit = compile(
+        &CompilationParams { literal_mapper },
+        vec![(Namespace::root(), module)],
+        &mut env,
+    )
+    .expect("compiled");
+    let ee = ExecutionEnviron::from_compile_environment(env.finalize());
+    let main = fun_id(&unit, "main");
+    let mut em = ExecutionMachine::new(&unit, &ee, execution_params(), ArenaAllocator::new(), ());
+
+    let result = werbolg_exec::exec(&mut em, main, &[]).expect("executed");
+    // the composite values are handles, resolved through the allocator
+    assert!(matches!(result, Value::Handle(_)));
+    let element = |i| em.allocator.index(&result, i).expect("list element");
+    let values = (0..3)
+        .map(|i| element(i).int().expect("integral"))
+        .collect::<Vec<_>>();
+    assert_eq!(values, vec![4, 12, 42]);
+    let (_, _, fields) = em.allocator.enumeration(element(3)).expect("rect");
+    let (_, _, inner) = em.allocator.enumeration(&fields[0]).expect("circle");
+    assert_eq!(inner[0].int().expect("integral"), 5);
+    assert_eq!(fields[1].int().expect("integral"), 2);
+
+    // the region lives as long as the machine, a new execution keeps the values of the previous one
+    let allocated = em.allocator.len();
+    let second = werbolg_exec::exec(&mut em, main, &[]).expect("executed");
+    assert_eq!(em.allocator.len(), 2 * allocated);
+    assert!(em.allocator.index(&result, 0).is_some());
+    assert!(matches!((&result, &second), (Value::Handle(a), Value::Handle(b)) if a != b));
+
+    // the handles wouldn't be valid for the machine restoring a snapshot
+    assert!(matches!(
+        em.snapshot(&VALUE_CODEC),
+        Err(SnapshotError::AllocatorNotCaptured)
+    ));
+}
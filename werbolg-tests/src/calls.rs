@@ -0,0 +1,80 @@
+//! Closures, lists and calls, in tail position or not
+
+use crate::fixture::*;
+use werbolg_compile::Instruction;
+
+#[test]
+fn closures_capture() {
+    let snippet = r#"
+    (define main
+        (define (sub a b) (- a b))
+        (define (sub_by n)
+            (define (inner m) (sub m n))
+            inner)
+        ((sub_by 8) 50)
+    )
+    "#;
+    assert_eq!(run_int(snippet), 42)
+}
+
+#[test]
+fn closures_recursive() {
+    let snippet = r#"
+    (define main
+        (define (sum n)
+            (if (eq n 0) 0 (+ n (sum (- n 1)))))
+        (define (count_by step)
+            (define (count n acc)
+                (if (eq n 0) acc (count (- n 1) (+ acc step))))
+            count)
+        (+ (sum 4) ((count_by 2) 16 0))
+    )
+    "#;
+    assert_eq!(run_int(snippet), 42);
+
+    // the recursive calls use the function value of their frame, only count_by creates a closure
+    let unit = compile_snippet(snippet);
+    let closures = unit
+        .code
+        .iter()
+        .filter(|(_, instr)| matches!(instr, Instruction::MakeClosure(_, _)))
+        .count();
+    assert_eq!(closures, 1);
+}
+
+#[test]
+fn list_construction() {
+    let snippet = r#"
+    (define main
+        (index (10 (+ 20 22) 30) 1)
+    )
+    "#;
+    assert_eq!(run_int(snippet), 42)
+}
+
+#[test]
+fn tail_calls_reuse_frame() {
+    let snippet = r#"
+    (define (count n acc)
+        (if (eq n 0) acc (count (- n 1) (+ acc 1))))
+    (define (loop n)
+        (if (eq n 0) (depth) (loop (- n 1))))
+    (define main
+        (+ (count 1000 0) (loop 1000))
+    )
+    "#;
+    // only the call from main to loop is active when loop calls depth
+    assert_eq!(run_int(snippet), 1001)
+}
+
+#[test]
+fn calls_restore_caller_locals() {
+    let snippet = r#"
+    (define (one) 1)
+    (define (add_one x)
+        (define (unused y) y)
+        (+ (one) x))
+    (define main (+ (add_one 10) 100))
+    "#;
+    assert_eq!(run_int(snippet), 111)
+}
@@ -0,0 +1,223 @@
+//! Enumerations, structures and the pattern matching on them
+
+use crate::fixture::*;
+use alloc::{boxed::Box, vec, vec::Vec};
+use werbolg_compile::{CompilationError, CompilationUnit, VariantIndex};
+use werbolg_core::{Expr, Ident, Literal, Module, Namespace, Path, Pattern, Spanned};
+use werbolg_exec::{ExecutionError, Valuable};
+
+#[test]
+fn enum_construction() {
+    let rect = Expr::Call(
+        0..0,
+        vec![
+            variant("shape", "rect"),
+            number("2"),
+            Expr::Call(
+                0..0,
+                vec![
+                    Expr::Path(0..0, Path::relative(Ident::from("+"))),
+                    number("1"),
+                    number("2"),
+                ],
+            ),
+        ],
+    );
+    let body = Expr::List(0..0, vec![rect, variant("shape", "empty")]);
+    let unit = compile_modules(vec![(Namespace::root(), shape_module(body))]).expect("compiled");
+    let decoded =
+        CompilationUnit::decode(&unit.encode(&LITERAL_CODEC), &LITERAL_CODEC).expect("decoded");
+
+    let Ok(Value::List(values)) = run_unit(&decoded) else {
+        panic!("expecting a list")
+    };
+    let Some((shape, VariantIndex(1), [Value::Integral(2), Value::Integral(3)])) =
+        values[0].enumeration()
+    else {
+        panic!("expecting a rect: {:?}", values[0])
+    };
+    assert!(matches!(values[1].enumeration(), Some((s, VariantIndex(2), [])) if s == shape));
+
+    let missing_field = Expr::Call(0..0, vec![variant("shape", "circle")]);
+    let err = compile_modules(vec![(Namespace::root(), shape_module(missing_field))])
+        .err()
+        .map(error_cause);
+    assert!(matches!(
+        err,
+        Some(CompilationError::ConstructorArityMismatch {
+            expected: 1,
+            got: 0,
+            ..
+        })
+    ));
+}
+
+#[test]
+fn match_patterns() {
+    let describe = Expr::Match {
+        span: 10..20,
+        expr: Box::new(local("s")),
+        arms: vec![
+            arm(
+                variant_pattern(
+                    "shape",
+                    "circle",
+                    vec![Pattern::Literal(Literal::number("0"))],
+                ),
+                number("100"),
+            ),
+            arm(
+                variant_pattern("shape", "circle", vec![bind("r")]),
+                local("r"),
+            ),
+            arm(
+                variant_pattern(
+                    "shape",
+                    "rect",
+                    vec![
+                        variant_pattern("shape", "circle", vec![bind("r")]),
+                        bind("h"),
+                    ],
+                ),
+                call(vec![local("+"), local("r"), local("h")]),
+            ),
+            arm(
+                variant_pattern(
+                    "shape",
+                    "rect",
+                    vec![bind("w"), Pattern::Literal(Literal::number("3"))],
+                ),
+                local("w"),
+            ),
+            arm(
+                variant_pattern("shape", "rect", vec![Pattern::Wildcard, bind("h")]),
+                call(vec![local("+"), local("h"), number("10")]),
+            ),
+        ],
+    };
+    let literal = Expr::Match {
+        span: 0..0,
+        expr: Box::new(number("3")),
+        arms: vec![
+            arm(Pattern::Literal(Literal::number("1")), number("10")),
+            arm(bind("x"), call(vec![local("+"), local("x"), number("1")])),
+        ],
+    };
+    let describe_call = |shape: Expr| call(vec![local("describe"), shape]);
+    let circle = |n: &str| call(vec![variant("shape", "circle"), number(n)]);
+    let rect = |w: Expr, h: &str| call(vec![variant("shape", "rect"), w, number(h)]);
+    let body = Expr::List(
+        0..0,
+        vec![
+            describe_call(circle("0")),
+            describe_call(circle("4")),
+            describe_call(rect(number("9"), "3")),
+            describe_call(rect(number("9"), "4")),
+            describe_call(rect(circle("5"), "2")),
+            literal,
+        ],
+    );
+    let mut module = shape_module(body);
+    module
+        .statements
+        .push(function("describe", &["s"], describe.clone()));
+    let module = Module::decode(&module.encode()).expect("decoded module");
+    let unit = compile_modules(vec![(Namespace::root(), module)]).expect("compiled");
+    let unit = CompilationUnit::decode(&unit.encode(&LITERAL_CODEC), &LITERAL_CODEC)
+        .expect("decoded unit");
+
+    let Ok(Value::List(values)) = run_unit(&unit) else {
+        panic!("expecting a list")
+    };
+    assert_eq!(ints(&values), vec![100, 4, 9, 14, 7, 4]);
+
+    let mut module = shape_module(describe_call(variant("shape", "empty")));
+    module
+        .statements
+        .push(function("describe", &["s"], describe));
+    let unit = compile_modules(vec![(Namespace::root(), module)]).expect("compiled");
+    assert!(matches!(
+        run_unit(&unit),
+        Err(ExecutionError::MatchFailure { span }) if span == (10..20)
+    ));
+}
+
+#[test]
+fn struct_construction() {
+    let point_path = || Spanned::new(0..0, Path::relative(Ident::from("point")));
+    let point = |fields: Vec<(&str, Expr)>| {
+        let fields = fields.into_iter().map(|(f, e)| (spanned(f), e)).collect();
+        Expr::Construct(0..0, point_path(), fields)
+    };
+    let field = |e: Expr, f: &str| Expr::Field(Box::new(e), point_path(), spanned(f));
+    let sum = call(vec![local("+"), number("1"), number("3")]);
+    let point_pattern = Pattern::Constructor(
+        point_path(),
+        vec![
+            Spanned::new(0..0, bind("x")),
+            Spanned::new(0..0, Pattern::Literal(Literal::number("2"))),
+        ],
+    );
+    let body = Expr::List(
+        0..0,
+        vec![
+            field(point(vec![("y", number("2")), ("x", sum.clone())]), "x"),
+            field(point(vec![("x", number("1")), ("y", number("2"))]), "y"),
+            Expr::Match {
+                span: 0..0,
+                expr: Box::new(point(vec![("y", number("2")), ("x", number("5"))])),
+                arms: vec![arm(point_pattern, local("x"))],
+            },
+        ],
+    );
+    let with_main = |body: Expr| {
+        let mut module = parse("(struct point (x y))");
+        module.statements.push(function("main", &[], body));
+        vec![(Namespace::root(), module)]
+    };
+    let Ok(Value::List(values)) = run_modules(with_main(body)) else {
+        panic!("expecting a list")
+    };
+    assert_eq!(ints(&values), vec![4, 2, 5]);
+
+    let missing = point(vec![("x", number("1"))]);
+    assert!(matches!(
+        compile_modules(with_main(missing)).err().map(error_cause),
+        Some(CompilationError::StructureFieldMissing(_, _, f)) if f.matches("y")
+    ));
+    let extra = point(vec![
+        ("x", number("1")),
+        ("y", number("2")),
+        ("z", number("3")),
+    ]);
+    assert!(matches!(
+        compile_modules(with_main(extra)).err().map(error_cause),
+        Some(CompilationError::StructureFieldNotExistant(_, _, f)) if f.matches("z")
+    ));
+}
+
+#[test]
+fn field_by_name() {
+    let point = Expr::Construct(
+        0..0,
+        Spanned::new(0..0, Path::relative(Ident::from("point"))),
+        vec![(spanned("x"), number("1")), (spanned("y"), number("2"))],
+    );
+    let with_main = |body: Expr| {
+        let mut module = parse("(struct point (x y))");
+        module.statements.push(function("main", &[], body));
+        vec![(Namespace::root(), module)]
+    };
+    let by_name = |f: &str| Expr::FieldByName(Box::new(point.clone()), spanned(f));
+
+    let unit = compile_modules(with_main(by_name("y"))).expect("no compilation error");
+    let decoded =
+        CompilationUnit::decode(&unit.encode(&LITERAL_CODEC), &LITERAL_CODEC).expect("decoded");
+    let value = run_unit(&decoded).expect("no execution error");
+    assert_eq!(value.int().expect("integral"), 2);
+
+    assert!(matches!(
+        run_modules(with_main(by_name("z"))),
+        Err(ExecutionError::StructFieldNotFound { field, .. }) if field.matches("z")
+    ));
+}
@@ -0,0 +1,265 @@
+//! Stack traces, debugger, execution hooks and profiler
+
+use crate::fixture::*;
+use alloc::{
+    string::{String, ToString},
+    vec,
+    vec::Vec,
+};
+use werbolg_compile::{
+    compile, CompilationParams, Environment, Instruction, InstructionAddress, ParamBindIndex,
+};
+use werbolg_core::{Ident, Namespace, NifId, Path, ValueFun};
+use werbolg_exec::{
+    DebugStop, Debugger, ExecutionEnviron, ExecutionError, ExecutionHooks, ExecutionMachine,
+    NIFCall, ProfileFrame, Profiler, NIF,
+};
+
+#[test]
+fn stack_trace() {
+    let snippet = r#"
+    (define (bad n) (index n 0))
+    (define (f n) (+ 1 (bad n)))
+    (define main (+ 1 (f 3)))
+    "#;
+    let unit = compile_snippet(snippet);
+    let ee = execution_environ();
+    let entry_point = fun_id(&unit, "main");
+    let mut em = machine(&unit, &ee);
+
+    let error = werbolg_exec::exec(&mut em, entry_point, &[]).expect_err("index error");
+    let ExecutionError::Traced { error, trace } = em.traced(error) else {
+        panic!("expecting a traced error")
+    };
+    assert!(matches!(*error, ExecutionError::ValueKindUnexpected { .. }));
+    let names = trace.iter().map(|f| f.to_string()).collect::<Vec<_>>();
+    assert_eq!(names, vec!["bad", "f", "main"]);
+
+    // every frame is located at the call it is executing
+    let calls = trace
+        .iter()
+        .map(|f| {
+            let (namespace, span) = f.location.clone().expect("location recorded");
+            assert!(namespace.is_root());
+            &snippet[span]
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(calls, vec!["(index n 0)", "(bad n)", "(f 3)"]);
+}
+
+#[test]
+fn debugger() {
+    let snippet = r#"
+    (define (count n acc) (if (eq n 0) acc (count (- n 1) (+ acc 1))))
+    (define main (+ 1 (count 3 0)))
+    "#;
+    let unit = compile_snippet(snippet);
+    let ee = execution_environ();
+    let path = |name: &str| Path::absolute(Ident::from(name));
+    let main = fun_id(&unit, "main");
+    let count = fun_id(&unit, "count");
+
+    let mut em = machine(&unit, &ee);
+    let mut debugger = Debugger::new();
+    let count_entry = debugger
+        .add_function_breakpoint(&unit, &path("count"))
+        .expect("count breakpoint");
+    assert!(werbolg_exec::initialize(&mut em, main, &[])
+        .expect("initialized")
+        .is_none());
+
+    // the breakpoint is hit at every recursive call
+    for n in [3, 2] {
+        let stop = debugger.run(&mut em).expect("no error");
+        assert!(matches!(stop, DebugStop::Breakpoint(ia) if ia == count_entry));
+        let frame = em.current_frame();
+        assert_eq!(frame.fun, Some(count));
+        assert_eq!(ints(frame.params), vec![n, 3 - n]);
+        assert_eq!(
+            frame.param(ParamBindIndex(1)).and_then(|v| v.int().ok()),
+            Some(n)
+        );
+    }
+
+    // stepping into the first instruction of count doesn't leave the function
+    assert!(matches!(debugger.step_into(&mut em), Ok(DebugStop::Step)));
+    assert_eq!(em.current_frame().fun, Some(count));
+
+    // stepping out returns to main, with the result of count on the top of its stack
+    assert!(debugger.remove_breakpoint(count_entry));
+    assert!(matches!(debugger.step_out(&mut em), Ok(DebugStop::Step)));
+    let frame = em.current_frame();
+    assert_eq!(frame.fun, Some(main));
+    assert_eq!(frame.operands.last().and_then(|v| v.int().ok()), Some(3));
+
+    // stepping over the native call to + stays in main
+    assert!(matches!(debugger.step_over(&mut em), Ok(DebugStop::Step)));
+    assert_eq!(em.current_frame().fun, Some(main));
+    match debugger.run(&mut em) {
+        Ok(DebugStop::Finished(value)) => assert_eq!(value.int().expect("integral"), 4),
+        _ => panic!("expecting the execution to finish"),
+    }
+    assert!(matches!(
+        debugger.step_into(&mut em),
+        Err(ExecutionError::ExecutionFinished)
+    ));
+}
+
+#[test]
+fn debugger_entry_and_local_functions() {
+    let snippet = r#"
+    (define main
+        (define (twice n) (+ n n))
+        (+ (twice 20) 2)
+    )
+    "#;
+    let unit = compile_snippet(snippet);
+    let ee = execution_environ();
+    let main = fun_id(&unit, "main");
+    // the local function is compiled as a lambda, only reachable by its FunId
+    let twice = unit
+        .funs
+        .iter()
+        .find(|(_, fundef)| fundef.name.is_none())
+        .map(|(fun_id, _)| fun_id)
+        .expect("twice function");
+
+    let mut em = machine(&unit, &ee);
+    let mut debugger = Debugger::new();
+    let main_entry = debugger
+        .add_function_breakpoint(&unit, &Path::absolute(Ident::from("main")))
+        .expect("main breakpoint");
+    let twice_entry = debugger
+        .add_fun_breakpoint(&unit, twice)
+        .expect("twice breakpoint");
+    assert!(werbolg_exec::initialize(&mut em, main, &[])
+        .expect("initialized")
+        .is_none());
+
+    // the breakpoint on the entry point is reported before executing it
+    let stop = debugger.run(&mut em).expect("no error");
+    assert!(matches!(stop, DebugStop::Breakpoint(ia) if ia == main_entry));
+    assert_eq!(em.current_frame().fun, Some(main));
+
+    // resuming executes the entry instruction and stops in the local function
+    let stop = debugger.run(&mut em).expect("no error");
+    assert!(matches!(stop, DebugStop::Breakpoint(ia) if ia == twice_entry));
+    assert_eq!(em.current_frame().fun, Some(twice));
+
+    match debugger.run(&mut em) {
+        Ok(DebugStop::Finished(value)) => assert_eq!(value.int().expect("integral"), 42),
+        _ => panic!("expecting the execution to finish"),
+    }
+}
+
+/// Hooks recording the events of an execution
+#[derive(Default)]
+struct Recorder {
+    instructions: usize,
+    calls: Vec<ValueFun>,
+    nifs: Vec<NifId>,
+    returns: Vec<u64>,
+}
+
+impl ExecutionHooks<Value> for Recorder {
+    fn on_instruction(&mut self, _ip: InstructionAddress, _instruction: &Instruction) {
+        self.instructions += 1;
+    }
+
+    fn on_call(&mut self, fun: ValueFun, _args: &[Value]) {
+        self.calls.push(fun)
+    }
+
+    fn on_nif(&mut self, nif: NifId) {
+        self.nifs.push(nif)
+    }
+
+    fn on_return(&mut self, value: &Value) {
+        self.returns.push(value.int().unwrap_or(u64::MAX))
+    }
+}
+
+#[test]
+fn execution_hooks() {
+    let snippet = r#"
+    (define (double n) (+ n n))
+    (define main (- (double (wait)) 1))
+    "#;
+    // the NIFs are typed with the hooks, so the hooks need their own environment
+    let mut env = Environment::new();
+    let mut nif_ids = Vec::new();
+    for (name, nif) in [
+        ("wait", nif_wait as fn(&[Value]) -> _),
+        ("+", nif_plus),
+        ("-", nif_sub),
+    ] {
+        let nif = NIF {
+            name,
+            call: NIFCall::Pure(nif),
+        };
+        nif_ids.push(env.add_nif(&Namespace::root(), Ident::from(name), nif));
+    }
+    let unit = compile(
+        &CompilationParams { literal_mapper },
+        vec![(Namespace::root(), parse(snippet))],
+        &mut env,
+    )
+    .expect("compiled");
+    let ee = ExecutionEnviron::from_compile_environment(env.finalize());
+    let params = execution_params();
+    let main = fun_id(&unit, "main");
+    let double = fun_id(&unit, "double");
+    let mut em =
+        ExecutionMachine::with_hooks(&unit, &ee, params, DummyAlloc, (), Recorder::default());
+    assert!(matches!(
+        werbolg_exec::exec(&mut em, main, &[]),
+        Err(ExecutionError::NotReady)
+    ));
+    em.resume_with(Value::Integral(5)).expect("suspended");
+    let value = werbolg_exec::exec_continue(&mut em).expect("no error");
+    assert_eq!(value.int().expect("integral"), 9);
+
+    let recorder = em.hooks;
+    assert!(recorder.instructions > 0);
+    assert_eq!(
+        recorder.calls,
+        vec![
+            ValueFun::Fun(main),
+            ValueFun::Native(nif_ids[0]),
+            ValueFun::Fun(double),
+            ValueFun::Native(nif_ids[1]),
+            ValueFun::Native(nif_ids[2]),
+        ]
+    );
+    assert_eq!(recorder.nifs, nif_ids);
+    // wait returns when resumed, the tail call to + returns for double, and the tail call to - for main
+    assert_eq!(recorder.returns, vec![5, 10, 10, 9, 9]);
+}
+
+#[test]
+fn profiler() {
+    let snippet = r#"
+    (define (double n) (+ n n))
+    (define (twice n) (double (double n)))
+    (define main (- (twice 3) 1))
+    "#;
+    let unit = compile_snippet(snippet);
+    let ee = ExecutionEnviron::from_compile_environment(environment_with_hooks().finalize());
+    let params = execution_params();
+    let fun = |name: &str| fun_id(&unit, name);
+    let mut em = ExecutionMachine::with_hooks(&unit, &ee, params, DummyAlloc, (), Profiler::new());
+    let value = werbolg_exec::exec(&mut em, fun("main"), &[]).expect("no error");
+    assert_eq!(value.int().expect("integral"), 11);
+
+    let profiler = &em.hooks;
+    let calls = |name: &str| profiler.counters()[&ProfileFrame::Fun(fun(name))].calls;
+    assert_eq!((calls("main"), calls("twice"), calls("double")), (1, 1, 2));
+
+    let mut folded = String::new();
+    profiler.write_folded(&mut folded, &em).expect("written");
+    let lines = folded.lines().collect::<Vec<_>>();
+    // double is called by twice, then tail called which replaces twice
+    assert!(lines.contains(&"main;twice;double;+ 1"));
+    assert!(lines.contains(&"main;double;+ 1"));
+    assert!(lines.contains(&"main;- 1"));
+}
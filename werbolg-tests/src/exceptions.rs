@@ -0,0 +1,83 @@
+//! Throwing and catching values, and the errors of the NIFs
+
+use crate::fixture::*;
+use alloc::{boxed::Box, vec};
+use werbolg_compile::CompilationUnit;
+use werbolg_core::{Expr, Module, Namespace};
+use werbolg_exec::ExecutionError;
+
+#[test]
+fn try_catch() {
+    let try_catch = |body: Expr, handler: Expr| Expr::Try {
+        span: 0..0,
+        body: Box::new(body),
+        binder: spanned("e"),
+        handler: Box::new(handler),
+    };
+    let throw = |expr: Expr| Expr::Throw(0..0, Box::new(expr));
+    let plus = |a: Expr, b: Expr| call(vec![local("+"), a, b]);
+
+    let main = Expr::List(
+        0..0,
+        vec![
+            plus(number("1000"), call(vec![local("safe"), number("2")])),
+            call(vec![local("rethrow"), number("5")]),
+            try_catch(plus(number("1"), call(vec![local("fail")])), local("e")),
+            try_catch(number("7"), local("e")),
+        ],
+    );
+    let module = Module {
+        statements: vec![
+            function("thrower", &["x"], throw(plus(local("x"), number("1")))),
+            function(
+                "deep",
+                &["x"],
+                plus(number("1"), call(vec![local("thrower"), local("x")])),
+            ),
+            function(
+                "safe",
+                &["x"],
+                try_catch(
+                    call(vec![local("deep"), local("x")]),
+                    plus(local("e"), number("100")),
+                ),
+            ),
+            function(
+                "rethrow",
+                &["x"],
+                try_catch(
+                    try_catch(throw(local("x")), throw(plus(local("e"), number("1")))),
+                    plus(local("e"), number("10")),
+                ),
+            ),
+            function("main", &[], main),
+        ],
+    };
+    let module = Module::decode(&module.encode()).expect("decoded module");
+    let unit = compile_modules(vec![(Namespace::root(), module)]).expect("compiled");
+    let unit = CompilationUnit::decode(&unit.encode(&LITERAL_CODEC), &LITERAL_CODEC)
+        .expect("decoded unit");
+
+    let Ok(Value::List(values)) = run_unit(&unit) else {
+        panic!("expecting a list")
+    };
+    // the handler of safe gets the value thrown 2 calls deeper, and the message length of the NIF error
+    assert_eq!(ints(&values), vec![1103, 16, 6, 7]);
+
+    let run_main = |main: Expr| {
+        let module = Module {
+            statements: vec![function("main", &[], main)],
+        };
+        run_modules(vec![(Namespace::root(), module)])
+    };
+    assert!(matches!(
+        run_main(throw(number("5"))),
+        Err(ExecutionError::UncaughtThrow { value_is: INT_KIND })
+    ));
+    // errors that are not catchable go through the handlers
+    let not_catchable = call(vec![local("index"), number("1"), number("0")]);
+    assert!(matches!(
+        run_main(try_catch(not_catchable, local("e"))),
+        Err(ExecutionError::ValueKindUnexpected { .. })
+    ));
+}
@@ -0,0 +1,510 @@
+//! Values, NIFs and helpers shared by the end to end tests
+
+use alloc::{string::String, vec, vec::Vec};
+use werbolg_compile::{
+    compile, CompilationError, CompilationParams, CompilationUnit, Environment, LiteralCodec,
+    NamespaceResolver, VariantIndex,
+};
+use werbolg_core::id::IdF;
+use werbolg_core::{ConstrId, FunId, Ident, Literal, Namespace, NifId, Path, ValueFun};
+use werbolg_core::{EnumDef, Expr, FunDef, Module, PathType, Privacy, Spanned, Statement};
+use werbolg_core::{MatchArm, Pattern, StructDef, Use, Variable, Variant};
+use werbolg_exec::{
+    instruction_cost, ArenaHandle, ArenaValuable, ExecutionEnviron, ExecutionError,
+    ExecutionMachine, ExecutionParams, NIFCall, Valuable, ValueCodec, ValueKind, WAllocator, NIF,
+};
+use werbolg_lang_common::FileUnit;
+
+#[derive(Clone, Debug)]
+pub enum Value {
+    Unit,
+    Bool(bool),
+    Integral(u64),
+    Fun(ValueFun),
+    Closure(FunId, Vec<Value>),
+    List(Vec<Value>),
+    Struct(ConstrId, Vec<Value>),
+    Enum(ConstrId, VariantIndex, Vec<Value>),
+    Handle(ArenaHandle),
+}
+
+pub(crate) const UNIT_KIND: ValueKind = b"    unit";
+
+pub(crate) const BOOL_KIND: ValueKind = b"    bool";
+
+pub(crate) const INT_KIND: ValueKind = b"     int";
+
+pub(crate) const FUN_KIND: ValueKind = b"     fun";
+
+pub(crate) const CLOSURE_KIND: ValueKind = b" closure";
+
+pub(crate) const LIST_KIND: ValueKind = b"    list";
+
+pub(crate) const STRUCT_KIND: ValueKind = b"  struct";
+
+pub(crate) const ENUM_KIND: ValueKind = b"    enum";
+
+pub(crate) const HANDLE_KIND: ValueKind = b"  handle";
+
+impl Value {
+    pub(crate) fn int(&self) -> Result<u64, ExecutionError> {
+        match self {
+            Value::Integral(n) => Ok(*n),
+            _ => Err(ExecutionError::ValueKindUnexpected {
+                value_expected: INT_KIND,
+                value_got: self.descriptor(),
+            }),
+        }
+    }
+}
+
+impl Valuable for Value {
+    fn descriptor(&self) -> ValueKind {
+        match self {
+            Value::Unit => UNIT_KIND,
+            Value::Bool(_) => BOOL_KIND,
+            Value::Integral(_) => INT_KIND,
+            Value::Fun(_) => FUN_KIND,
+            Value::Closure(_, _) => CLOSURE_KIND,
+            Value::List(_) => LIST_KIND,
+            Value::Struct(_, _) => STRUCT_KIND,
+            Value::Enum(_, _, _) => ENUM_KIND,
+            Value::Handle(_) => HANDLE_KIND,
+        }
+    }
+
+    fn conditional(&self) -> Option<bool> {
+        match self {
+            Value::Bool(b) => Some(*b),
+            _ => None,
+        }
+    }
+
+    fn fun(&self) -> Option<ValueFun> {
+        match self {
+            Value::Fun(fun) => Some(*fun),
+            _ => None,
+        }
+    }
+
+    fn closure(&self) -> Option<(FunId, &[Self])> {
+        match self {
+            Value::Closure(fun, captured) => Some((*fun, captured)),
+            _ => None,
+        }
+    }
+
+    fn structure(&self) -> Option<(ConstrId, &[Self])> {
+        match self {
+            Value::Struct(constr, fields) => Some((*constr, fields)),
+            _ => None,
+        }
+    }
+
+    fn enumeration(&self) -> Option<(ConstrId, VariantIndex, &[Self])> {
+        match self {
+            Value::Enum(constr, variant, fields) => Some((*constr, *variant, fields)),
+            _ => None,
+        }
+    }
+
+    fn index(&self, index: usize) -> Option<&Self> {
+        match self {
+            Value::List(elements) => elements.get(index),
+            _ => None,
+        }
+    }
+
+    fn make_fun(fun: ValueFun) -> Self {
+        Value::Fun(fun)
+    }
+
+    fn make_closure(fun: FunId, captured: Vec<Self>) -> Self {
+        Value::Closure(fun, captured)
+    }
+
+    fn make_list(elements: Vec<Self>) -> Self {
+        Value::List(elements)
+    }
+
+    fn make_struct(constr: ConstrId, fields: Vec<Self>) -> Self {
+        Value::Struct(constr, fields)
+    }
+
+    fn make_enum(constr: ConstrId, variant: VariantIndex, fields: Vec<Self>) -> Self {
+        Value::Enum(constr, variant, fields)
+    }
+
+    fn make_dummy() -> Self {
+        Value::Unit
+    }
+}
+
+impl ArenaValuable for Value {
+    fn make_handle(handle: ArenaHandle) -> Self {
+        Value::Handle(handle)
+    }
+
+    fn handle(&self) -> Option<ArenaHandle> {
+        match self {
+            Value::Handle(handle) => Some(*handle),
+            _ => None,
+        }
+    }
+}
+
+pub(crate) struct DummyAlloc;
+
+impl WAllocator for DummyAlloc {
+    type Value = Value;
+}
+
+pub(crate) fn nif_plus(args: &[Value]) -> Result<Value, ExecutionError> {
+    Ok(Value::Integral(args[0].int()? + args[1].int()?))
+}
+
+pub(crate) fn nif_sub(args: &[Value]) -> Result<Value, ExecutionError> {
+    Ok(Value::Integral(args[0].int()? - args[1].int()?))
+}
+
+pub(crate) fn nif_eq(args: &[Value]) -> Result<Value, ExecutionError> {
+    Ok(Value::Bool(args[0].int()? == args[1].int()?))
+}
+
+pub(crate) fn nif_index(args: &[Value]) -> Result<Value, ExecutionError> {
+    let index = args[1].int()? as usize;
+    args[0]
+        .index(index)
+        .cloned()
+        .ok_or(ExecutionError::ValueKindUnexpected {
+            value_expected: LIST_KIND,
+            value_got: args[0].descriptor(),
+        })
+}
+
+/// Return the number of call frames currently active in the machine
+pub(crate) fn nif_depth<H>(
+    em: &mut ExecutionMachine<DummyAlloc, u64, (), Value, H>,
+) -> Result<Value, ExecutionError> {
+    Ok(Value::Integral(em.rets.len() as u64))
+}
+
+/// Suspend the execution, the host is expected to resume it with a value
+pub(crate) fn nif_wait(_args: &[Value]) -> Result<Value, ExecutionError> {
+    Err(ExecutionError::NotReady)
+}
+
+pub(crate) fn nif_fail(_args: &[Value]) -> Result<Value, ExecutionError> {
+    Err(ExecutionError::UserPanic {
+        message: String::from("failed"),
+    })
+}
+
+pub(crate) fn literal_mapper(lit: Literal) -> Result<u64, CompilationError> {
+    match lit {
+        Literal::Number(n) => n
+            .parse()
+            .map_err(|_| CompilationError::LiteralNotSupported(Literal::Number(n))),
+        _ => Err(CompilationError::LiteralNotSupported(lit)),
+    }
+}
+
+pub(crate) const LITERAL_CODEC: LiteralCodec<u64> = LiteralCodec {
+    encode: |lit, out| out.extend_from_slice(&lit.to_le_bytes()),
+    decode: |bytes| bytes.try_into().ok().map(u64::from_le_bytes),
+};
+
+pub(crate) fn literal_to_value(lit: &u64) -> Value {
+    Value::Integral(*lit)
+}
+
+pub(crate) fn literal_matches(lit: &u64, value: &Value) -> bool {
+    matches!(value, Value::Integral(v) if v == lit)
+}
+
+pub(crate) fn execution_params() -> ExecutionParams<u64, Value> {
+    ExecutionParams {
+        literal_to_value,
+        literal_matches,
+        instruction_cost,
+        max_call_depth: 1024,
+        max_stack_size: 65536,
+        catchable,
+    }
+}
+
+/// NIF errors are caught, with the length of their message as value
+pub(crate) fn catchable(error: &ExecutionError) -> Option<Value> {
+    match error {
+        ExecutionError::UserPanic { message } => Some(Value::Integral(message.len() as u64)),
+        _ => None,
+    }
+}
+
+pub(crate) type TestNIF<'m, 'e, H = ()> = NIF<'m, 'e, DummyAlloc, u64, (), Value, H>;
+
+pub(crate) fn environment<'m, 'e>() -> Environment<TestNIF<'m, 'e>, Value> {
+    environment_with_hooks()
+}
+
+/// The test environment, for a machine with hooks
+pub(crate) fn environment_with_hooks<'m, 'e, H>() -> Environment<TestNIF<'m, 'e, H>, Value> {
+    let mut env = Environment::new();
+    for (name, nif) in [
+        ("+", nif_plus as fn(&[Value]) -> _),
+        ("-", nif_sub),
+        ("eq", nif_eq),
+        ("index", nif_index),
+        ("wait", nif_wait),
+        ("fail", nif_fail),
+    ] {
+        let nif = NIF {
+            name,
+            call: NIFCall::Pure(nif),
+        };
+        env.add_nif(&Namespace::root(), Ident::from(name), nif);
+    }
+    let depth = NIF {
+        name: "depth",
+        call: NIFCall::Raw(nif_depth),
+    };
+    env.add_nif(&Namespace::root(), Ident::from("depth"), depth);
+    env
+}
+
+pub(crate) fn parse(snippet: &str) -> Module {
+    let fileunit = FileUnit::from_str("test", snippet);
+    werbolg_lang_lispy::module(&fileunit).expect("no parse error")
+}
+
+/// Add a use statement at the top of a module
+pub(crate) fn with_use(
+    mut module: Module,
+    namespace: &str,
+    hiding: &[&str],
+    renames: &[(&str, &str)],
+) -> Module {
+    let u = Use {
+        namespace: Ident::from(namespace),
+        hiding: hiding.iter().map(|h| Ident::from(*h)).collect(),
+        renames: renames
+            .iter()
+            .map(|(from, to)| (Ident::from(*from), Ident::from(*to)))
+            .collect(),
+    };
+    module.statements.insert(0, Statement::Use(u));
+    module
+}
+
+/// Make the function of a module private
+pub(crate) fn with_private(mut module: Module, name: &str) -> Module {
+    for stmt in module.statements.iter_mut() {
+        if let Statement::Function(_, fundef) = stmt {
+            if fundef.name.as_ref().is_some_and(|n| n.matches(name)) {
+                fundef.privacy = Privacy::Private;
+            }
+        }
+    }
+    module
+}
+
+/// The execution environment of the test NIFs
+pub(crate) fn execution_environ<'m, 'e>() -> TestEnviron<'m, 'e> {
+    ExecutionEnviron::from_compile_environment(environment().finalize())
+}
+
+/// Compile a snippet as the module of the root namespace
+pub(crate) fn compile_snippet(snippet: &str) -> CompilationUnit<u64> {
+    compile_modules(vec![(Namespace::root(), parse(snippet))]).expect("compiled")
+}
+
+pub(crate) fn compile_modules(
+    modules: Vec<(Namespace, Module)>,
+) -> Result<CompilationUnit<u64>, CompilationError> {
+    let params = CompilationParams { literal_mapper };
+    compile(&params, modules, &mut environment())
+}
+
+/// Compile the modules, and execute the `main` function of the root namespace
+pub(crate) fn run_modules(modules: Vec<(Namespace, Module)>) -> Result<Value, ExecutionError> {
+    let unit = compile_modules(modules).expect("no compilation error");
+    run_unit(&unit)
+}
+
+/// Execute the `main` function of the root namespace of a compiled unit
+pub(crate) fn run_unit(unit: &CompilationUnit<u64>) -> Result<Value, ExecutionError> {
+    let ee = execution_environ();
+    let mut em = ExecutionMachine::new(unit, &ee, execution_params(), DummyAlloc, ());
+    werbolg_exec::exec(&mut em, fun_id(unit, "main"), &[])
+}
+
+/// Get the function of the root namespace with this name
+pub(crate) fn fun_id(unit: &CompilationUnit<u64>, name: &str) -> FunId {
+    unit.funs_tbl
+        .get(
+            &NamespaceResolver::none(),
+            &Path::absolute(Ident::from(name)),
+        )
+        .expect("function")
+}
+
+/// The execution environment of the test NIFs, for a machine without hooks
+pub(crate) type TestEnviron<'m, 'e> = ExecutionEnviron<'m, 'e, DummyAlloc, u64, (), Value>;
+
+/// A machine executing a compiled unit with the test NIFs and execution parameters
+pub(crate) fn machine<'m, 'e>(
+    unit: &'m CompilationUnit<u64>,
+    ee: &'e TestEnviron<'m, 'e>,
+) -> ExecutionMachine<'m, 'e, DummyAlloc, u64, (), Value> {
+    ExecutionMachine::new(unit, ee, execution_params(), DummyAlloc, ())
+}
+
+/// Get the integral values of a list of values
+pub(crate) fn ints(values: &[Value]) -> Vec<u64> {
+    values.iter().map(|v| v.int().expect("integral")).collect()
+}
+
+pub(crate) fn run_modules_int(modules: Vec<(Namespace, Module)>) -> u64 {
+    match run_modules(modules) {
+        Ok(value) => value.int().expect("integral result"),
+        Err(e) => panic!("execution failed: {:?}", e),
+    }
+}
+
+pub(crate) fn run_int(snippet: &str) -> u64 {
+    run_modules_int(vec![(Namespace::root(), parse(snippet))])
+}
+
+/// Get the compilation error without the contexts added around it
+pub(crate) fn error_cause(e: CompilationError) -> CompilationError {
+    match e {
+        CompilationError::Context(_, e) => error_cause(*e),
+        e => e,
+    }
+}
+
+pub(crate) fn math_module() -> (Namespace, Module) {
+    let snippet = r#"
+    (define (double x) (+ x x))
+    (define (inc x) (+ x 1))
+    (define (double_inc x) (double (inc x)))
+    "#;
+    (
+        Namespace::root().append(Ident::from("math")),
+        parse(snippet),
+    )
+}
+
+pub(crate) fn spanned(ident: &str) -> Spanned<Ident> {
+    Spanned::new(0..0, Ident::from(ident))
+}
+
+pub(crate) fn number(n: &str) -> Expr {
+    Expr::Literal(0..0, Literal::number(n))
+}
+
+pub(crate) fn variant(enum_name: &str, variant: &str) -> Expr {
+    let idents = vec![Ident::from(enum_name), Ident::from(variant)];
+    Expr::Path(0..0, Path::new_raw(PathType::Relative, idents))
+}
+
+/// A module with the enumeration `shape { circle(r), rect(w h), empty }` and a main function
+pub(crate) fn shape_module(main_body: Expr) -> Module {
+    let shape = EnumDef {
+        name: spanned("shape"),
+        variants: [
+            ("circle", &["r"][..]),
+            ("rect", &["w", "h"]),
+            ("empty", &[]),
+        ]
+        .iter()
+        .map(|(name, fields)| {
+            Variant(StructDef {
+                name: spanned(name),
+                fields: fields.iter().map(|f| spanned(f)).collect(),
+            })
+        })
+        .collect(),
+    };
+    Module {
+        statements: vec![
+            Statement::Enum(0..0, shape),
+            function("main", &[], main_body),
+        ],
+    }
+}
+
+pub(crate) fn function(name: &str, vars: &[&str], body: Expr) -> Statement {
+    let fundef = FunDef {
+        privacy: Privacy::Public,
+        name: Some(Ident::from(name)),
+        vars: vars.iter().map(|v| Variable(spanned(v))).collect(),
+        body,
+    };
+    Statement::Function(0..0, fundef)
+}
+
+pub(crate) fn local(ident: &str) -> Expr {
+    Expr::Path(0..0, Path::relative(Ident::from(ident)))
+}
+
+pub(crate) fn call(exprs: Vec<Expr>) -> Expr {
+    Expr::Call(0..0, exprs)
+}
+
+pub(crate) fn arm(pattern: Pattern, body: Expr) -> MatchArm {
+    MatchArm {
+        pattern: Spanned::new(0..0, pattern),
+        body,
+    }
+}
+
+pub(crate) fn variant_pattern(enum_name: &str, variant: &str, patterns: Vec<Pattern>) -> Pattern {
+    let idents = vec![Ident::from(enum_name), Ident::from(variant)];
+    let path = Spanned::new(0..0, Path::new_raw(PathType::Relative, idents));
+    let patterns = patterns
+        .into_iter()
+        .map(|p| Spanned::new(0..0, p))
+        .collect();
+    Pattern::Constructor(path, patterns)
+}
+
+pub(crate) fn bind(ident: &str) -> Pattern {
+    Pattern::Bind(Ident::from(ident))
+}
+
+/// Encode the values which are not composite, which are enough for the snapshot tests
+pub(crate) const VALUE_CODEC: ValueCodec<Value> = ValueCodec {
+    encode: |value, out| match value {
+        Value::Unit => out.push(0),
+        Value::Bool(b) => out.extend_from_slice(&[1, *b as u8]),
+        Value::Integral(n) => {
+            out.push(2);
+            out.extend_from_slice(&n.to_le_bytes())
+        }
+        Value::Fun(ValueFun::Native(nif)) => {
+            out.push(3);
+            out.extend_from_slice(&(nif.as_index() as u32).to_le_bytes())
+        }
+        Value::Fun(ValueFun::Fun(fun)) => {
+            out.push(4);
+            out.extend_from_slice(&(fun.as_index() as u32).to_le_bytes())
+        }
+        _ => out.push(0xff),
+    },
+    decode: |bytes| {
+        let (tag, rest) = bytes.split_first()?;
+        let id = || rest.try_into().ok().map(|b| u32::from_le_bytes(b) as usize);
+        match tag {
+            0 => Some(Value::Unit),
+            1 => Some(Value::Bool(*rest.first()? != 0)),
+            2 => rest
+                .try_into()
+                .ok()
+                .map(|b| Value::Integral(u64::from_le_bytes(b))),
+            3 => id().map(|i| Value::Fun(ValueFun::Native(NifId::from_collection_len(i)))),
+            4 => id().map(|i| Value::Fun(ValueFun::Fun(FunId::from_collection_len(i)))),
+            _ => None,
+        }
+    },
+};
@@ -4,6 +4,28 @@ extern crate proc_macro;
 
 use werbolg_ir_write::module;
 
+#[cfg(test)]
+mod fixture;
+
+#[cfg(test)]
+mod allocators;
+#[cfg(test)]
+mod calls;
+#[cfg(test)]
+mod constructors;
+#[cfg(test)]
+mod debugging;
+#[cfg(test)]
+mod exceptions;
+#[cfg(test)]
+mod limits;
+#[cfg(test)]
+mod namespaces;
+#[cfg(test)]
+mod serialization;
+#[cfg(test)]
+mod suspension;
+
 pub fn module1() -> werbolg_core::Module {
     module! {
         fn add(a, b) {
@@ -0,0 +1,81 @@
+//! Fuel metering and stack overflow protection
+
+use crate::fixture::*;
+use werbolg_core::Ident;
+use werbolg_exec::{ExecutionError, ExecutionMachine, ExecutionParams};
+
+#[test]
+fn fuel_metering() {
+    let snippet = r#"
+    (define (loop n) (loop (+ n 1)))
+    (define (count n acc) (if (eq n 0) acc (count (- n 1) (+ acc 2))))
+    (define main (count 100 0))
+    (define forever (loop 0))
+    "#;
+    let unit = compile_snippet(snippet);
+    let ee = execution_environ();
+    let entry = |name: &str| fun_id(&unit, name);
+
+    // an infinite loop stops when the fuel runs out
+    let mut em = machine(&unit, &ee);
+    em.set_fuel(Some(10_000));
+    assert!(matches!(
+        werbolg_exec::exec(&mut em, entry("forever"), &[]),
+        Err(ExecutionError::OutOfFuel { .. })
+    ));
+
+    // a metered execution can be topped up and resumed until it finishes
+    let mut em = machine(&unit, &ee);
+    em.set_fuel(Some(50));
+    let mut refuels = 0;
+    let mut result = werbolg_exec::exec(&mut em, entry("main"), &[]);
+    while let Err(ExecutionError::OutOfFuel { .. }) = result {
+        refuels += 1;
+        em.add_fuel(50);
+        result = werbolg_exec::exec_continue(&mut em);
+    }
+    assert!(refuels > 1);
+    assert_eq!(result.expect("finished").int().expect("integral"), 200);
+    assert!(matches!(
+        werbolg_exec::exec_continue(&mut em),
+        Err(ExecutionError::ExecutionFinished)
+    ));
+}
+
+#[test]
+fn stack_overflow() {
+    let snippet = r#"
+    (define (deep n) (+ 1 (deep n)))
+    (define main (deep 0))
+    "#;
+    let unit = compile_snippet(snippet);
+    let ee = execution_environ();
+    let main = fun_id(&unit, "main");
+    let run = |params: ExecutionParams<u64, Value>| {
+        let mut em = ExecutionMachine::new(&unit, &ee, params, DummyAlloc, ());
+        werbolg_exec::exec(&mut em, main, &[])
+    };
+
+    // the call depth limit, main tail calls deep so its frame is replaced
+    match run(execution_params()) {
+        Err(ExecutionError::StackOverflow { depth, trace }) => {
+            assert_eq!(depth, 1024);
+            assert_eq!(trace.len(), 1025);
+            assert!(trace
+                .iter()
+                .all(|frame| frame.name == Some(Ident::from("deep"))));
+        }
+        r => panic!("expecting a stack overflow: {:?}", r),
+    }
+
+    // the value stack limit, every call frame keeps the + function and 1 on the stack
+    let params = ExecutionParams {
+        max_call_depth: usize::MAX,
+        max_stack_size: 100,
+        ..execution_params()
+    };
+    match run(params) {
+        Err(ExecutionError::StackOverflow { depth, .. }) => assert!(depth < 100),
+        r => panic!("expecting a stack overflow: {:?}", r),
+    }
+}
@@ -0,0 +1,21 @@
+//! Imports and privacy of the functions across namespaces
+
+use crate::fixture::*;
+use alloc::vec;
+use werbolg_core::Namespace;
+
+#[test]
+fn use_renames() {
+    let main = parse("(define main (+ (twice (inc 10)) (double_inc 9)))");
+    let main = with_use(main, "math", &[], &[("double", "twice")]);
+    let modules = vec![math_module(), (Namespace::root(), main)];
+    assert_eq!(run_modules_int(modules), 42)
+}
+
+#[test]
+fn private_functions() {
+    // a private function is called from its own namespace
+    let root = parse("(define (secret x) (+ x 1)) (define main (secret 41))");
+    let root = with_private(root, "secret");
+    assert_eq!(run_modules_int(vec![(Namespace::root(), root)]), 42);
+}
@@ -0,0 +1,44 @@
+//! Execution of the decoded compilation units and modules
+
+use crate::fixture::*;
+use alloc::vec;
+use werbolg_compile::CompilationUnit;
+use werbolg_core::{Module, Namespace};
+
+#[test]
+fn unit_serialization() {
+    let snippet = r#"
+    (define (count n acc)
+        (if (eq n 0) acc (count (- n 1) (+ acc 1))))
+    (define main
+        (define (add_to n) (define (inner m) (+ m n)) inner)
+        ((add_to 2) (count 40 0))
+    )
+    "#;
+    let math = math_module();
+    let unit = compile_modules(vec![math, (Namespace::root(), parse(snippet))]).expect("compiled");
+
+    // a decoded unit executes like the original one
+    let decoded =
+        CompilationUnit::decode(&unit.encode(&LITERAL_CODEC), &LITERAL_CODEC).expect("decoded");
+    assert_eq!(run_unit(&decoded).ok().and_then(|v| v.int().ok()), Some(42));
+}
+
+#[test]
+fn module_serialization() {
+    let snippet = r#"
+    (define (helper n) (if (eq n 0) (1 2 3) (helper (- n 1))))
+    (define main
+        (define (add_to n) (define (inner m) (+ m n)) inner)
+        ((add_to (index (helper 3) 1)) (double 20))
+    )
+    "#;
+    let module = with_private(with_use(parse(snippet), "math", &["inc"], &[]), "helper");
+
+    // a decoded module compiles and runs like the original one
+    let decoded = Module::decode(&module.encode()).expect("decoded");
+    assert_eq!(
+        run_modules_int(vec![math_module(), (Namespace::root(), decoded)]),
+        42
+    );
+}
@@ -0,0 +1,167 @@
+//! Suspension of the execution, snapshots and scheduling of tasks
+
+use crate::fixture::*;
+use alloc::{vec, vec::Vec};
+use core::num::NonZeroUsize;
+use werbolg_compile::{compile, CompilationParams};
+use werbolg_core::{id::IdF, Namespace};
+use werbolg_exec::{
+    ExecutionEnviron, ExecutionError, ExecutionMachine, Scheduler, SchedulerNifs, SchedulerParams,
+    SnapshotError, TaskEvent, TaskId, TaskState,
+};
+
+#[test]
+fn nif_suspension() {
+    let snippet = r#"
+    (define (tail_wait n) (wait n))
+    (define main (+ (wait 1) (tail_wait 2)))
+    "#;
+    let unit = compile_snippet(snippet);
+    let ee = execution_environ();
+    let entry_point = fun_id(&unit, "main");
+    let mut em = machine(&unit, &ee);
+
+    assert!(matches!(
+        werbolg_exec::exec(&mut em, entry_point, &[]),
+        Err(ExecutionError::NotReady)
+    ));
+    // continuing without resolving the pending call doesn't make progress
+    assert!(matches!(
+        werbolg_exec::exec_continue(&mut em),
+        Err(ExecutionError::NotReady)
+    ));
+    em.resume_with(Value::Integral(40)).expect("suspended");
+
+    // the second call is in tail position of tail_wait
+    assert!(matches!(
+        werbolg_exec::exec_continue(&mut em),
+        Err(ExecutionError::NotReady)
+    ));
+    em.resume_with(Value::Integral(2)).expect("suspended");
+
+    let result = werbolg_exec::exec_continue(&mut em).expect("finished");
+    assert_eq!(result.int().expect("integral"), 42);
+    assert!(matches!(
+        em.resume_with(Value::Integral(0)),
+        Err(ExecutionError::NotSuspended)
+    ));
+}
+
+#[test]
+fn snapshot_restore() {
+    let snippet = r#"
+    (define (add_wait n) (+ n (wait n)))
+    (define main (+ 1 (add_wait 20)))
+    "#;
+    let unit = compile_snippet(snippet);
+    let ee = execution_environ();
+    let main = fun_id(&unit, "main");
+    let fingerprint = unit.fingerprint();
+
+    let mut em = machine(&unit, &ee);
+    assert!(matches!(
+        werbolg_exec::exec(&mut em, main, &[]),
+        Err(ExecutionError::NotReady)
+    ));
+    let snapshot = em.snapshot(&VALUE_CODEC).expect("captured");
+    drop(em);
+
+    // restore against the same unit compiled again
+    let same_unit = compile_snippet(snippet);
+    assert_eq!(same_unit.fingerprint(), fingerprint);
+    let mut em = machine(&same_unit, &ee);
+    em.restore(&snapshot, &VALUE_CODEC).expect("restored");
+    em.resume_with(Value::Integral(21)).expect("suspended");
+    let value = werbolg_exec::exec_continue(&mut em).expect("finished");
+    assert_eq!(value.int().expect("integral"), 42);
+
+    // a snapshot can't be restored for another unit
+    let other_unit = compile_snippet("(define main 1)");
+    let other_fingerprint = other_unit.fingerprint();
+    let mut em = machine(&other_unit, &ee);
+    assert!(matches!(
+        em.restore(&snapshot, &VALUE_CODEC),
+        Err(SnapshotError::FingerprintMismatch { expected, got })
+            if expected == other_fingerprint && got == fingerprint
+    ));
+}
+
+#[test]
+fn scheduler_tasks() {
+    let snippet = r#"
+    (define (worker parent n) (send parent (+ (receive) n)))
+    (define (worker_by n) (define (work parent) (send parent (+ (receive) n))) work)
+    (define (waiter) (+ 1 (wait 0)))
+    (define (sum_replies sent1 sent2) (+ (receive) (receive)))
+    (define (count n acc) (if (eq n 0) acc (count (- n 1) (+ acc 2))))
+    (define main
+        (sum_replies (send (spawn worker (self) 1) 10) (send (spawn (worker_by 2) (self)) 20)))
+    "#;
+    let mut env = environment();
+    let nifs = SchedulerNifs::register(&mut env, &Namespace::root());
+    let params = CompilationParams { literal_mapper };
+    let modules = vec![(Namespace::root(), parse(snippet))];
+    let unit = compile(&params, modules, &mut env).expect("compiled");
+    let ee = ExecutionEnviron::from_compile_environment(env.finalize());
+    let fun = |name: &str| fun_id(&unit, name);
+
+    let params = SchedulerParams {
+        slice: NonZeroUsize::new(3).expect("not zero"),
+        nifs,
+        new_machine: |module, environ| {
+            let mut em = ExecutionMachine::new(module, environ, execution_params(), DummyAlloc, ());
+            em.set_fuel(Some(100));
+            em
+        },
+        task_to_value: |task| Value::Integral(task.as_index() as u64),
+        value_to_task: |value| match value {
+            Value::Integral(n) => Some(TaskId::from_collection_len(*n as usize)),
+            _ => None,
+        },
+    };
+    let mut scheduler = Scheduler::new(&unit, &ee, params);
+    let main = scheduler.spawn(fun("main"), &[]).expect("spawned");
+    let waiter = scheduler.spawn(fun("waiter"), &[]).expect("spawned");
+    let counter = scheduler
+        .spawn(fun("count"), &[Value::Integral(100), Value::Integral(0)])
+        .expect("spawned");
+
+    let mut finished = Vec::new();
+    let mut refuels = 0;
+    while let Some(event) = scheduler.run() {
+        match event {
+            TaskEvent::Finished(task, value) => finished.push((task, value.int().expect("int"))),
+            TaskEvent::Parked(task) => {
+                assert_eq!(task, waiter);
+                scheduler.wake(task, Value::Integral(41)).expect("woken")
+            }
+            TaskEvent::OutOfFuel(task) => {
+                // a task out of fuel is parked, but not on a call to wake it from
+                refuels += 1;
+                assert_eq!(scheduler.state(task), Some(TaskState::Parked));
+                assert!(matches!(
+                    scheduler.wake(task, Value::Unit),
+                    Err(ExecutionError::NotSuspended)
+                ));
+                scheduler.add_fuel(task, 100).expect("refuelled")
+            }
+            TaskEvent::Failed(task, error) => panic!("task {:?} failed: {:?}", task, error),
+        }
+    }
+    finished.sort();
+    assert!(refuels > 1);
+
+    // the workers, of a function and of a closure, return the message they sent to main
+    let w1 = TaskId::from_collection_len(3);
+    let w2 = TaskId::from_collection_len(4);
+    assert_eq!(
+        finished,
+        vec![(main, 33), (waiter, 42), (counter, 200), (w1, 11), (w2, 22)]
+    );
+    assert_eq!(scheduler.state(main), Some(TaskState::Finished));
+    assert!(matches!(scheduler.send(main, Value::Unit), Ok(false)));
+    assert!(matches!(
+        scheduler.wake(w1, Value::Unit),
+        Err(ExecutionError::NotSuspended)
+    ));
+}